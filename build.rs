@@ -0,0 +1,8 @@
+//! Bakes the client id from `secret/id` into the bot if the file exists, so it still builds without one.
+
+fn main() {
+    println!("cargo:rerun-if-changed=secret/id");
+    if let Ok(id) = std::fs::read_to_string("secret/id") {
+        println!("cargo:rustc-env=ROULETTE_CLIENT_ID={}", id.trim());
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use twitch_api::types::{UserId, UserName};
+
+/// A timeout the bot gives someone, written to the `--audit-log` once twitch accepted it.
+#[derive(Debug, Serialize)]
+pub struct Timeout<'a> {
+    /// Id of the broadcaster whose chat the user is timed out in
+    pub channel_id: &'a UserId,
+    /// Login of the broadcaster
+    pub channel: &'a UserName,
+    pub user_id: &'a UserId,
+    pub user: &'a UserName,
+    /// Length of the timeout in seconds, in the log only as long as it lasts before `--unban-after` lifts it
+    pub duration: u32,
+    /// Reason shown to the user and in the mod view
+    pub reason: &'a str,
+    /// Key of the command that timed the user out, e.g. `roulette`
+    pub command: &'static str,
+}
+
+/// A timeout read back from the log, for `?!modlog`.
+#[derive(Debug, Deserialize)]
+pub struct Entry {
+    pub timestamp: chrono::DateTime<chrono::FixedOffset>,
+    pub channel_id: UserId,
+    pub user: UserName,
+    pub duration: u32,
+    pub command: String,
+}
+
+/// Append-only log of the timeouts the bot gave, as json lines.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    /// Size after which the log is moved to `<path>.1` and a new one is started
+    max_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    timeout: &'a Timeout<'a>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        Self { path, max_bytes }
+    }
+
+    /// Add a timeout to the log.
+    pub fn record(&self, timeout: &Timeout<'_>) -> Result<(), eyre::Report> {
+        let mut line = serde_json::to_string(&Line {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            timeout,
+        })?;
+        line.push('\n');
+
+        let size = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("couldn't read audit log {}", self.path.display()))
+            }
+        };
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            // Keep one old log around, the one before that is replaced.
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, &rotated)
+                .wrap_err_with(|| format!("couldn't rotate audit log {}", self.path.display()))?;
+        }
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .wrap_err_with(|| format!("couldn't write audit log {}", self.path.display()))
+    }
+
+    /// The last `count` timeouts given in `channel`, newest first.
+    ///
+    /// The rotated log is read too, so the latest timeouts are still there right after a rotation. Lines that
+    /// can't be read, like one cut off by a crash, are skipped.
+    pub fn recent(&self, channel: &UserId, count: usize) -> Result<Vec<Entry>, eyre::Report> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        let mut recent = std::collections::VecDeque::with_capacity(count + 1);
+        for path in [PathBuf::from(rotated), self.path.clone()] {
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e)
+                        .wrap_err_with(|| format!("couldn't read audit log {}", path.display()))
+                }
+            };
+            for entry in text
+                .lines()
+                .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+                .filter(|entry| entry.channel_id == *channel)
+            {
+                recent.push_back(entry);
+                if recent.len() > count {
+                    recent.pop_front();
+                }
+            }
+        }
+        Ok(recent.into_iter().rev().collect())
+    }
+}
@@ -0,0 +1,585 @@
+use twitch_api::eventsub::channel::ChannelChatMessageV1Payload;
+
+use crate::Cli;
+
+/// Every command the bot answers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Roulette,
+    Help,
+    Rules,
+    Flip,
+    Safety,
+    Say,
+    Enable,
+    Disable,
+    MergeStats,
+    Top,
+    Rank,
+    Profile,
+    Record,
+    GiveUp,
+    Pause,
+    Resume,
+    Coinflip,
+    Accept,
+    Stats,
+    Fair,
+    Reveal,
+    Ignore,
+    Unignore,
+    Protect,
+    Unprotect,
+    Insure,
+    Shield,
+    Replay,
+    Diag,
+    Tournament,
+    Enter,
+    Leave,
+    Luck,
+    Double,
+    ModLog,
+    Lang,
+    Link,
+    Unlink,
+    SetPoints,
+    AddPoints,
+}
+
+impl Command {
+    /// Commands with a fixed name, in the order `help` lists them.
+    pub const BUILTIN: &'static [(&'static str, Command)] = &[
+        ("help", Command::Help),
+        ("rules", Command::Rules),
+        ("flip", Command::Flip),
+        ("stats", Command::Stats),
+        ("top", Command::Top),
+        ("rank", Command::Rank),
+        ("record", Command::Record),
+        ("fair", Command::Fair),
+        ("giveup", Command::GiveUp),
+        ("insure", Command::Insure),
+        ("shield", Command::Shield),
+        ("double", Command::Double),
+        ("coinflip", Command::Coinflip),
+        ("accept", Command::Accept),
+        ("replay", Command::Replay),
+        ("lang", Command::Lang),
+        ("enter", Command::Enter),
+        ("leave", Command::Leave),
+        ("safety", Command::Safety),
+        ("profile", Command::Profile),
+        ("tournament", Command::Tournament),
+        ("luck", Command::Luck),
+        ("modlog", Command::ModLog),
+        ("pause", Command::Pause),
+        ("resume", Command::Resume),
+        ("reveal", Command::Reveal),
+        ("say", Command::Say),
+        ("enable", Command::Enable),
+        ("disable", Command::Disable),
+        ("ignore", Command::Ignore),
+        ("unignore", Command::Unignore),
+        ("protect", Command::Protect),
+        ("unprotect", Command::Unprotect),
+        ("link", Command::Link),
+        ("unlink", Command::Unlink),
+        ("mergestats", Command::MergeStats),
+        ("setpoints", Command::SetPoints),
+        ("addpoints", Command::AddPoints),
+        ("diag", Command::Diag),
+    ];
+
+    /// Look up the command called `name` in chat, in any case unless `--case-sensitive-commands` is set.
+    pub fn parse(name: &str, opts: &Cli) -> Option<Self> {
+        let matches = |command: &str| {
+            if opts.case_sensitive_commands {
+                command == name
+            } else {
+                command.to_lowercase() == name.to_lowercase()
+            }
+        };
+        if matches(&opts.command_name) {
+            return Some(Command::Roulette);
+        }
+        Self::BUILTIN
+            .iter()
+            .find(|(builtin, _)| matches(builtin))
+            .map(|(_, command)| *command)
+    }
+
+    /// Look up a command by its name in chat or its [key](Self::key).
+    pub fn find(name: &str, opts: &Cli) -> Option<Self> {
+        Self::parse(name, opts).or_else(|| Self::from_key(name).ok())
+    }
+
+    /// Look up a command by its [key](Self::key), for options naming commands.
+    pub fn from_key(key: &str) -> Result<Self, String> {
+        if key == "roulette" {
+            return Ok(Command::Roulette);
+        }
+        Self::BUILTIN
+            .iter()
+            .find(|(builtin, _)| *builtin == key)
+            .map(|(_, command)| *command)
+            .ok_or_else(|| format!("there's no command called `{key}`"))
+    }
+
+    /// Name identifying the command that doesn't change with `--command-name`.
+    pub fn key(self) -> &'static str {
+        match self {
+            Command::Roulette => "roulette",
+            _ => Self::BUILTIN
+                .iter()
+                .find(|(_, command)| *command == self)
+                .map(|(name, _)| *name)
+                .expect("every command except roulette is builtin"),
+        }
+    }
+
+    /// Name of the command in chat, without the prefix.
+    pub fn name(self, opts: &Cli) -> &str {
+        match self {
+            Command::Roulette => &opts.command_name,
+            _ => self.key(),
+        }
+    }
+
+    /// Who may use the command, unless `--command-role` says otherwise.
+    pub fn role(self) -> Role {
+        match self {
+            Command::Safety
+            | Command::Pause
+            | Command::Resume
+            | Command::Reveal
+            | Command::Say
+            | Command::Enable
+            | Command::Disable
+            | Command::Ignore
+            | Command::Unignore
+            | Command::Protect
+            | Command::Unprotect
+            | Command::Link
+            | Command::Unlink
+            | Command::SetPoints
+            | Command::AddPoints
+            | Command::MergeStats
+            | Command::Tournament
+            | Command::Luck
+            | Command::Profile
+            | Command::ModLog => Role::Moderator,
+            Command::Diag => Role::Broadcaster,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Whether the command can time someone out, these are ignored with `--readonly`.
+    pub fn times_out(self) -> bool {
+        matches!(
+            self,
+            Command::Roulette
+                | Command::GiveUp
+                | Command::Coinflip
+                | Command::Accept
+                | Command::Tournament
+                | Command::Enter
+                | Command::Double
+        )
+    }
+
+    /// Whether the command can be turned off, `enable` can't be or there'd be no way back.
+    pub fn can_disable(self) -> bool {
+        !matches!(self, Command::Enable | Command::Disable)
+    }
+
+    /// Short explanation for `help`, if the name doesn't say enough, `prefix` being the channel's command prefix.
+    pub fn description(self, opts: &Cli, prefix: &str) -> Option<String> {
+        match self {
+            Command::Roulette => Some(format!(
+                "{} in {} chance of a timeout",
+                opts.bullets, opts.chambers
+            )),
+            Command::Rules => Some("how roulette works, with the odds right now".to_owned()),
+            Command::Flip => Some("call heads or tails".to_owned()),
+            Command::Safety => Some("mods: on, off or auto".to_owned()),
+            Command::Pause => Some(format!("mods: pause roulette until {prefix}resume")),
+            Command::Resume => Some(format!("mods: undo {prefix}pause")),
+            Command::Say => Some("mods: post a message as the bot".to_owned()),
+            Command::Enable | Command::Disable => Some("mods: turn a command on or off".to_owned()),
+            Command::Ignore | Command::Unignore => {
+                Some("mods: stop or start answering someone, like another bot".to_owned())
+            }
+            Command::GiveUp => Some(format!(
+                "time yourself out, for {}s unless you pick up to {}s",
+                opts.giveup_timeout, opts.giveup_max
+            )),
+            Command::Coinflip => Some(format!(
+                "challenge someone, the loser is timed out for {}s",
+                opts.coinflip_timeout
+            )),
+            Command::Insure => Some(format!(
+                "spend {} points to insure your next spin",
+                opts.insurance_cost
+            )),
+            Command::Shield => Some(if opts.shield_cost == 0 {
+                "use a shield to skip your next cooldown, mods: give @user one".to_owned()
+            } else {
+                format!(
+                    "spend {} points on a shield that skips your next cooldown, mods: give @user one",
+                    opts.shield_cost
+                )
+            }),
+            Command::Double => Some(format!(
+                "right after surviving, spin at {}% odds to double your streak and points",
+                (opts.double_odds * 100.0).round()
+            )),
+            Command::Accept => Some(format!("accept a {prefix}coinflip challenge")),
+            Command::Replay => Some(format!("recap the last {prefix}coinflip")),
+            Command::Lang => Some("pick the language of your spins".to_owned()),
+            Command::Luck => Some("mods: bless (+) or curse (-) someone's next spins".to_owned()),
+            Command::Tournament => Some("mods: start or cancel a roulette tournament".to_owned()),
+            Command::Enter => Some("join the tournament before it starts".to_owned()),
+            Command::Leave => Some("drop out of the tournament".to_owned()),
+            Command::Protect | Command::Unprotect => {
+                Some("mods: make someone immune to roulette, or not anymore".to_owned())
+            }
+            Command::Link => Some("mods: make an alt share someone's cooldown".to_owned()),
+            Command::Unlink => Some("mods: undo a link".to_owned()),
+            Command::Stats => Some("yours, @user's or global for the channel".to_owned()),
+            Command::Fair => Some("check that spins aren't rigged".to_owned()),
+            Command::Reveal => {
+                Some("mods: reveal the seed of the fair round and start a new one".to_owned())
+            }
+            Command::Top => Some("most survivals across all channels".to_owned()),
+            Command::Rank => Some(match opts.rank_by {
+                crate::stats::RankBy::Survivals => "your place in the survivals ranking".to_owned(),
+                crate::stats::RankBy::Deaths => "your place in the deaths ranking".to_owned(),
+            }),
+            Command::Record => Some("the channel's longest run of survivors".to_owned()),
+            Command::Diag => Some("broadcaster: check on the bot".to_owned()),
+            Command::ModLog => Some("mods: the bot's last timeouts, or the last n".to_owned()),
+            Command::SetPoints => Some("mods: set someone's points".to_owned()),
+            Command::AddPoints => Some("mods: give or take (-) someone's points".to_owned()),
+            Command::MergeStats => Some("mods: move one account's stats to another".to_owned()),
+            Command::Profile => Some("mods: switch the channel's profile".to_owned()),
+            Command::Help => None,
+        }
+    }
+
+    /// How the arguments of the command are written, for the reply to a use that's missing them or gets them
+    /// wrong.
+    pub fn usage(self, opts: &Cli) -> Option<String> {
+        let usage = match self {
+            Command::Flip => "[heads|tails]",
+            Command::Safety => "on|off|auto",
+            Command::Say => "<message>",
+            Command::Enable | Command::Disable => "<command>",
+            Command::Ignore | Command::Unignore => "<user>",
+            Command::Protect | Command::Unprotect => "<user>",
+            Command::GiveUp => return Some(format!("[1-{}]", opts.giveup_max)),
+            Command::Coinflip => "@user",
+            Command::Stats => "[@user]",
+            Command::Rank => "[@user]",
+            Command::Shield => "[@user]",
+            Command::Luck => "@user +|-|off",
+            Command::Link => "<alt> <main>",
+            Command::Unlink => "<alt>",
+            Command::SetPoints | Command::AddPoints => "<user> <points>",
+            Command::MergeStats => "<from> <to>",
+            Command::Profile => "[<profile>|off|default]",
+            Command::ModLog => return Some(format!("[1-{}]", crate::MODLOG_MAX)),
+            Command::Roulette
+            | Command::Help
+            | Command::Rules
+            | Command::Top
+            | Command::Record
+            | Command::Pause
+            | Command::Resume
+            | Command::Accept
+            | Command::Fair
+            | Command::Reveal
+            | Command::Insure
+            | Command::Replay
+            | Command::Diag
+            | Command::Tournament
+            | Command::Enter
+            | Command::Leave
+            | Command::Double
+            | Command::Lang => return None,
+        };
+        Some(usage.to_owned())
+    }
+
+    /// All commands, in the order `help` lists them.
+    pub fn all() -> impl Iterator<Item = Command> {
+        std::iter::once(Command::Roulette).chain(Self::BUILTIN.iter().map(|(_, command)| *command))
+    }
+}
+
+/// Who someone is in a channel, each role including the ones before it.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+}
+
+impl Role {
+    /// The highest role of the chatter, from their badges.
+    pub fn of(payload: &ChannelChatMessageV1Payload) -> Self {
+        if payload.chatter_user_id == payload.broadcaster_user_id {
+            return Role::Broadcaster;
+        }
+        payload
+            .badges
+            .iter()
+            .map(|badge| match badge.set_id.as_str() {
+                "broadcaster" => Role::Broadcaster,
+                "moderator" => Role::Moderator,
+                "vip" => Role::Vip,
+                "subscriber" | "founder" => Role::Subscriber,
+                _ => Role::Everyone,
+            })
+            .max()
+            .unwrap_or(Role::Everyone)
+    }
+}
+
+/// The role a command needs in place of its usual one, written as `command=role`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandRole {
+    pub command: Command,
+    pub role: Role,
+}
+
+impl std::str::FromStr for CommandRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (command, role) = s
+            .split_once('=')
+            .ok_or_else(|| format!("`{s}` is not a command and role like flip=subscriber"))?;
+        Ok(Self {
+            command: Command::from_key(command.trim())?,
+            role: clap::ValueEnum::from_str(role.trim(), true)
+                .map_err(|_| format!("`{role}` is not a role, like everyone, subscriber, vip, moderator or broadcaster"))?,
+        })
+    }
+}
+
+/// A command prefix for a single channel, written as `login=prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelPrefix {
+    pub login: twitch_api::types::UserName,
+    pub prefix: String,
+}
+
+impl std::str::FromStr for ChannelPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (login, prefix) = s
+            .split_once('=')
+            .ok_or_else(|| format!("`{s}` is not a channel and prefix like alice=!r"))?;
+        Ok(Self {
+            login: login.trim().to_lowercase().into(),
+            prefix: parse_prefix(prefix)?,
+        })
+    }
+}
+
+/// Check a command prefix, which can't be empty or the bot would take every message for a command.
+pub fn parse_prefix(prefix: &str) -> Result<String, String> {
+    if prefix.is_empty() {
+        return Err("the command prefix can't be empty".to_owned());
+    }
+    if prefix.contains(char::is_whitespace) {
+        return Err(format!("the command prefix `{prefix}` can't have spaces"));
+    }
+    Ok(prefix.to_owned())
+}
+
+/// Check a `--command-namespace`, a single word so it can't be confused with the command after it.
+pub fn parse_namespace(namespace: &str) -> Result<String, String> {
+    if namespace.is_empty() || namespace.contains(char::is_whitespace) {
+        return Err(format!(
+            "the command namespace `{namespace}` has to be one word"
+        ));
+    }
+    Ok(namespace.to_owned())
+}
+
+/// The command a chat message starts with and the rest of the message, if it starts with `prefix`.
+///
+/// `reply_to` is the login of the message's parent if it's a reply, and the `namespace` word after the prefix
+/// is skipped if there is one.
+pub fn split_command<'a>(
+    text: &'a str,
+    reply_to: Option<&str>,
+    prefix: &str,
+    namespace: Option<&str>,
+) -> Option<(&'a str, Option<&'a str>)> {
+    // Phone keyboards like to put a space in front, `/me` messages can have one too.
+    let text = text.trim_start();
+    let text = strip_action(text).map_or(text, str::trim_start);
+    let text = match reply_to {
+        Some(parent) => strip_reply_mention(text, parent),
+        None => text,
+    };
+    let command = text.strip_prefix(prefix)?;
+    let command = namespace
+        .and_then(|namespace| {
+            command
+                .strip_prefix(namespace)?
+                .strip_prefix(char::is_whitespace)
+        })
+        .map_or(command, str::trim_start);
+    Some(match command.split_once(char::is_whitespace) {
+        Some((command, rest)) => (command, Some(rest.trim()).filter(|r| !r.is_empty())),
+        None => (command, None),
+    })
+}
+
+/// A command name without the `--trailing-punctuation` people put after it, so `?!roulette!` still plays.
+pub fn trim_punctuation<'a>(name: &'a str, punctuation: &str) -> &'a str {
+    name.trim_end_matches(|c| punctuation.contains(c))
+}
+
+/// The text of a reply without the `@parent` mention twitch puts in front of it, so a command sent as a reply
+/// still starts with the prefix.
+pub fn strip_reply_mention<'a>(text: &'a str, parent_login: &str) -> &'a str {
+    text.strip_prefix('@')
+        .and_then(|rest| {
+            let (mention, rest) = rest.split_once(char::is_whitespace)?;
+            mention
+                .eq_ignore_ascii_case(parent_login)
+                .then(|| rest.trim_start())
+        })
+        .unwrap_or(text)
+}
+
+/// The text of a `/me` message without the action markers, or `None` if it's a normal message.
+///
+/// Twitch passes action messages on the way IRC does, as `\u{1}ACTION text\u{1}`.
+pub fn strip_action(text: &str) -> Option<&str> {
+    let text = text.strip_prefix("\u{1}ACTION ")?;
+    Some(text.strip_suffix('\u{1}').unwrap_or(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_messages_are_commands_too() {
+        assert_eq!(
+            split_command("\u{1}ACTION ?!roulette\u{1}", None, "?!", None),
+            Some(("roulette", None))
+        );
+        assert_eq!(
+            split_command("\u{1}ACTION ?!stats @bob\u{1}", None, "?!", None),
+            Some(("stats", Some("@bob")))
+        );
+        assert_eq!(strip_action("?!roulette"), None);
+    }
+
+    fn opts(args: &[&str]) -> Cli {
+        clap::Parser::parse_from(std::iter::once("roulette").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn punctuation_after_a_command_is_ignored() {
+        let opts = opts(&[]);
+        for text in ["?!roulette!", "?!roulette.", "?!roulette?!", "?!roulette,"] {
+            let (name, rest) = split_command(text, None, "?!", None).unwrap();
+            assert_eq!(rest, None);
+            assert_eq!(
+                Command::parse(trim_punctuation(name, &opts.trailing_punctuation), &opts),
+                Some(Command::Roulette),
+                "{text}"
+            );
+        }
+        let (name, rest) = split_command("?!stats! @Bob!", None, "?!", None).unwrap();
+        assert_eq!(trim_punctuation(name, &opts.trailing_punctuation), "stats");
+        assert_eq!(rest, Some("@Bob!"));
+    }
+
+    #[test]
+    fn only_the_configured_punctuation_is_trimmed() {
+        let opts = opts(&["--trailing-punctuation", "!"]);
+        assert_eq!(
+            trim_punctuation("roulette!!", &opts.trailing_punctuation),
+            "roulette"
+        );
+        assert_eq!(
+            Command::parse(
+                trim_punctuation("roulette.", &opts.trailing_punctuation),
+                &opts
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn commands_match_in_any_case() {
+        let opts = opts(&[]);
+        for name in ["ROULETTE", "Roulette", "rOuLeTtE"] {
+            assert_eq!(
+                Command::parse(name, &opts),
+                Some(Command::Roulette),
+                "{name}"
+            );
+        }
+        assert_eq!(Command::parse("StAtS", &opts), Some(Command::Stats));
+        // The arguments keep their case.
+        assert_eq!(
+            split_command("?!COINFLIP @BoB", None, "?!", None),
+            Some(("COINFLIP", Some("@BoB")))
+        );
+    }
+
+    #[test]
+    fn case_sensitive_commands_only_match_their_name() {
+        let opts = opts(&["--case-sensitive-commands"]);
+        assert_eq!(Command::parse("roulette", &opts), Some(Command::Roulette));
+        assert_eq!(Command::parse("ROULETTE", &opts), None);
+        assert_eq!(Command::parse("Stats", &opts), None);
+    }
+
+    #[test]
+    fn commands_sent_as_replies_are_answered() {
+        assert_eq!(
+            split_command("@Bob ?!roulette", Some("bob"), "?!", None),
+            Some(("roulette", None))
+        );
+        assert_eq!(
+            split_command("@bob ?!coinflip @carol", Some("bob"), "?!", None),
+            Some(("coinflip", Some("@carol")))
+        );
+        // Only the mention of the parent is taken off.
+        assert_eq!(
+            split_command("@carol ?!roulette", Some("bob"), "?!", None),
+            None
+        );
+        assert_eq!(strip_reply_mention("@bob", "bob"), "@bob");
+    }
+
+    #[test]
+    fn a_leading_space_is_ignored() {
+        assert_eq!(
+            split_command(" ?!roulette", None, "?!", None),
+            Some(("roulette", None))
+        );
+        assert_eq!(
+            split_command("\t  ?!stats bob", None, "?!", None),
+            Some(("stats", Some("bob")))
+        );
+        assert_eq!(
+            split_command(" @bob  ?!roulette", Some("bob"), "?!", None),
+            Some(("roulette", None))
+        );
+    }
+}
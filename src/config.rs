@@ -0,0 +1,315 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, FromArgMatches};
+use eyre::WrapErr;
+
+use crate::Cli;
+
+/// Config file used when `--config` isn't given.
+const DEFAULT_PATH: &str = "config.toml";
+
+/// Parse the command line, using the values in the config file as defaults.
+///
+/// Every key in the file sets the flag of the same name, so `cooldown = 30` in the file is the same as
+/// passing `--cooldown 30`. Flags and environment variables take precedence over the file.
+pub fn parse() -> Result<Cli, eyre::Report> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let (path, explicit) = match config_path(&args) {
+        Some(path) => (path, true),
+        None => (PathBuf::from(DEFAULT_PATH), false),
+    };
+
+    let mut command = Cli::command();
+    match std::fs::read_to_string(&path) {
+        Ok(s) => {
+            let table: toml::Table = toml::from_str(&s)
+                .wrap_err_with(|| format!("couldn't parse config file {}", path.display()))?;
+            // Every bad key is listed at once, rather than one per try.
+            let mut problems = vec![];
+            for (key, value) in table {
+                let id = key.replace('-', "_");
+                if !command.get_arguments().any(|a| a.get_id() == id.as_str()) {
+                    problems.push(format!("there's no option called `{key}`"));
+                    continue;
+                }
+                let values = match value {
+                    toml::Value::Table(languages) if id == "translation" => translations(languages),
+                    toml::Value::Table(profiles) if id == "channel_profile" => {
+                        channel_profiles(profiles)
+                    }
+                    toml::Value::Array(values) => values
+                        .into_iter()
+                        .map(|v| to_arg(&key, v))
+                        .collect::<Result<Vec<_>, _>>(),
+                    value => to_arg(&key, value).map(|value| vec![value]),
+                };
+                match values {
+                    Ok(values) => command = command.mut_arg(id, |arg| arg.default_values(values)),
+                    Err(e) => problems.push(e.to_string()),
+                }
+            }
+            if !problems.is_empty() {
+                eyre::bail!(report(
+                    &format!("problems in config file {}", path.display()),
+                    &problems
+                ));
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {}
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("couldn't read config file {}", path.display()))
+        }
+    }
+
+    Ok(Cli::from_arg_matches(&command.get_matches_from(args))?)
+}
+
+/// Find the config file given with `--config` or the `CONFIG` environment variable.
+///
+/// This has to happen before clap parses the arguments, since the file decides their defaults.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("CONFIG").map(PathBuf::from)
+}
+
+/// Turn a value from the config file into the text it would be on the command line.
+fn to_arg(key: &str, value: toml::Value) -> Result<String, eyre::Report> {
+    Ok(match value {
+        toml::Value::String(s) => s,
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            eyre::bail!("`{key}` in the config file must be a single value or a list of values")
+        }
+    })
+}
+
+/// Turn `[translation.es]` sections of the config file into `--translation` values, one per message.
+fn translations(languages: toml::Table) -> Result<Vec<String>, eyre::Report> {
+    let mut values = vec![];
+    for (language, messages) in languages {
+        let toml::Value::Table(messages) = messages else {
+            eyre::bail!(
+                "`translation.{language}` in the config file must be a section of messages"
+            );
+        };
+        for (key, text) in messages {
+            let toml::Value::String(text) = text else {
+                eyre::bail!("`translation.{language}.{key}` in the config file must be a string");
+            };
+            values.push(format!("{language} {key}={text}"));
+        }
+    }
+    Ok(values)
+}
+
+/// Turn `[channel_profile.hardcore]` sections of the config file into `--channel-profile` values, one per
+/// setting.
+fn channel_profiles(profiles: toml::Table) -> Result<Vec<String>, eyre::Report> {
+    let mut values = vec![];
+    for (name, settings) in profiles {
+        let toml::Value::Table(settings) = settings else {
+            eyre::bail!(
+                "`channel_profile.{name}` in the config file must be a section of settings"
+            );
+        };
+        for (key, value) in settings {
+            let value = match value {
+                toml::Value::Array(values) => values
+                    .into_iter()
+                    .map(|v| to_arg(&key, v))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+                value => to_arg(&key, value)?,
+            };
+            values.push(format!("{name} {key}={value}"));
+        }
+    }
+    Ok(values)
+}
+
+/// Check the options for mistakes clap can't catch, since they're about several options at once or the config
+/// file could have set them.
+///
+/// Every problem is reported at once, so a config can be fixed in one go.
+pub fn validate(opts: &Cli) -> Result<(), eyre::Report> {
+    let mut problems = vec![];
+    if opts.bullets >= opts.chambers {
+        problems.push(format!(
+            "--bullets ({}) must be less than --chambers ({}), or everyone gets shot",
+            opts.bullets, opts.chambers
+        ));
+    }
+    for profile in &opts.profile {
+        let bullets = profile.bullets.unwrap_or(opts.bullets);
+        let chambers = profile.chambers.unwrap_or(opts.chambers);
+        if bullets >= chambers {
+            problems.push(format!(
+                "the profile {} has {bullets} bullets in {chambers} chambers, there have to be fewer bullets",
+                profile.name
+            ));
+        }
+    }
+    let channel_profiles = crate::profiles::collect(&opts.channel_profile);
+    for (name, profile) in &channel_profiles {
+        let bullets = profile.bullets.unwrap_or(opts.bullets);
+        let chambers = profile.chambers.unwrap_or(opts.chambers);
+        if bullets >= chambers {
+            problems.push(format!(
+                "the channel profile {name} has {bullets} bullets in {chambers} chambers, there have to be fewer bullets"
+            ));
+        }
+    }
+    for assignment in &opts.assign_profile {
+        if !opts.broadcaster_login.contains(&assignment.login) {
+            problems.push(format!(
+                "--assign-profile is set for {}, which isn't in --broadcaster-login",
+                assignment.login
+            ));
+        }
+        if !channel_profiles.contains_key(&assignment.profile) {
+            problems.push(format!(
+                "--assign-profile gives {} the profile {}, which isn't a --channel-profile",
+                assignment.login, assignment.profile
+            ));
+        }
+    }
+    match (opts.timeout_min, opts.timeout_max) {
+        (Some(min), Some(max)) if min > max => problems.push(format!(
+            "--timeout-min ({min}) can't be more than --timeout-max ({max})"
+        )),
+        (Some(_), None) | (None, Some(_)) => {
+            problems.push("--timeout-min and --timeout-max go together".to_owned())
+        }
+        _ => {}
+    }
+    if opts.thinking_delay_min_ms > opts.thinking_delay_max_ms {
+        problems.push(format!(
+            "--thinking-delay-min-ms ({}) can't be more than --thinking-delay-max-ms ({})",
+            opts.thinking_delay_min_ms, opts.thinking_delay_max_ms
+        ));
+    }
+    // Not required by clap, since it can't tell when the config file sets it. Exporting stats doesn't connect.
+    if opts.broadcaster_login.is_empty() && opts.export_stats.is_none() {
+        problems.push("--broadcaster-login is required".to_owned());
+    }
+    for channel in &opts.channel_command_prefix {
+        if !opts.broadcaster_login.contains(&channel.login) {
+            problems.push(format!(
+                "--channel-command-prefix is set for {}, which isn't in --broadcaster-login",
+                channel.login
+            ));
+        }
+    }
+    if let Some(login) = &opts.practice_channel {
+        if !opts.broadcaster_login.iter().any(|l| l.as_str() == login) {
+            problems.push(format!(
+                "--practice-channel is {login}, which isn't in --broadcaster-login"
+            ));
+        }
+    }
+    if opts.error_alerts == crate::ErrorAlerts::LogChannel && opts.log_channel.is_none() {
+        problems.push("--error-alerts log-channel needs --log-channel".to_owned());
+    }
+    if !opts.spin_reward.is_empty() && opts.transport != crate::Transport::Eventsub {
+        problems.push("--spin-reward needs --transport eventsub".to_owned());
+    }
+    if !opts.refund_redemption.is_empty() && opts.spin_reward.is_empty() {
+        problems.push("--refund-redemption is set without a --spin-reward".to_owned());
+    }
+    for (i, cooldown) in opts.outcome_cooldown.iter().enumerate() {
+        if opts.outcome_cooldown[..i]
+            .iter()
+            .any(|c| c.outcome == cooldown.outcome)
+        {
+            problems.push(format!(
+                "--outcome-cooldown has more than one cooldown for {}",
+                cooldown.outcome
+            ));
+        }
+    }
+    if opts.survival_cooldown < 1.0
+        && opts
+            .outcome_cooldown
+            .iter()
+            .any(|c| c.outcome == crate::Outcome::Survived.key())
+    {
+        problems.push(
+            "--survival-cooldown and --outcome-cooldown survived=... both set the cooldown after surviving"
+                .to_owned(),
+        );
+    }
+    // Command names match in any case, so the --command-name would take the place of the builtin.
+    if !opts.case_sensitive_commands {
+        if let Some((builtin, _)) = crate::Command::BUILTIN
+            .iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(&opts.command_name))
+        {
+            problems.push(format!(
+                "--command-name {} is the built-in command {builtin}, unless --case-sensitive-commands is set",
+                opts.command_name
+            ));
+        }
+    }
+    if opts.vip_losing_streak > 0 && opts.vip_streak == 0 {
+        problems.push("--vip-losing-streak is set without a --vip-streak".to_owned());
+    }
+    if opts.app_token && opts.client_secret.is_none() {
+        problems.push("--app-token needs --client-secret".to_owned());
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    eyre::bail!(report("problems with the options", &problems))
+}
+
+/// List problems one per line, each option being a flag or the key of the same name in the config file.
+fn report(title: &str, problems: &[String]) -> String {
+    let mut report = format!("{} {title}:", problems.len());
+    for problem in problems {
+        report.push_str("\n  - ");
+        report.push_str(problem);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn opts(args: &[&str]) -> Cli {
+        Cli::parse_from(
+            ["roulette", "--broadcaster-login", "alice"]
+                .iter()
+                .chain(args),
+        )
+    }
+
+    #[test]
+    fn the_command_name_cant_be_a_builtin_in_another_case() {
+        assert!(validate(&opts(&[])).is_ok());
+        assert!(validate(&opts(&["--command-name", "spin"])).is_ok());
+        assert!(validate(&opts(&["--command-name", "Help"])).is_err());
+        assert!(validate(&opts(&["--command-name", "STATS"])).is_err());
+        assert!(validate(&opts(&[
+            "--command-name",
+            "STATS",
+            "--case-sensitive-commands"
+        ]))
+        .is_ok());
+    }
+}
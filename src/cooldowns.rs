@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use twitch_api::types::UserId;
+
+/// A cooldown as it's kept in the `--cooldown-file`, by when it ends since an [`Instant`] means nothing after a
+/// restart.
+#[derive(Debug, Serialize, Deserialize)]
+struct Saved {
+    channel: UserId,
+    user: UserId,
+    ends: DateTime<Utc>,
+}
+
+/// Start a cooldown of `cooldown` for `key` at `now`, or return how much is left of the one that's running.
+pub fn start(
+    cooldowns: &mut HashMap<(UserId, UserId), Instant>,
+    key: &(UserId, UserId),
+    cooldown: Duration,
+    now: Instant,
+) -> Option<Duration> {
+    match cooldowns.get(key) {
+        Some(ends) if *ends > now => Some(*ends - now),
+        _ => {
+            cooldowns.insert(key.clone(), now + cooldown);
+            None
+        }
+    }
+}
+
+/// Read the cooldowns in `path`, keyed by broadcaster and user with when they end like the bot keeps them.
+/// Cooldowns that ended in the meantime are dropped, and there are none if the file doesn't exist yet.
+///
+/// None is longer than `longest` seconds, the longest cooldown there is now.
+pub fn load(path: &Path, longest: u64) -> Result<HashMap<(UserId, UserId), Instant>, eyre::Report> {
+    let saved: Vec<Saved> = match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s)
+            .wrap_err_with(|| format!("couldn't parse cooldown file {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => {
+            return Err(e)
+                .wrap_err_with(|| format!("couldn't read cooldown file {}", path.display()))
+        }
+    };
+    let now = Utc::now();
+    let cooldowns: HashMap<_, _> = saved
+        .into_iter()
+        .filter_map(|saved| {
+            let left = (saved.ends - now).to_std().ok()?;
+            // A cooldown that got shorter since is only as long as it is now.
+            let ends = Instant::now() + left.min(Duration::from_secs(longest));
+            Some(((saved.channel, saved.user), ends))
+        })
+        .collect();
+    tracing::info!(
+        "loaded {} running cooldown(s) from {}",
+        cooldowns.len(),
+        path.display()
+    );
+    Ok(cooldowns)
+}
+
+/// Write the cooldowns that haven't ended to `path`.
+pub fn save(
+    path: &Path,
+    cooldowns: &HashMap<(UserId, UserId), Instant>,
+) -> Result<(), eyre::Report> {
+    let now = Utc::now();
+    let saved: Vec<_> = cooldowns
+        .iter()
+        .filter_map(|((channel, user), ends)| {
+            let left = ends.checked_duration_since(Instant::now())?;
+            Some(Saved {
+                channel: channel.clone(),
+                user: user.clone(),
+                ends: now + chrono::Duration::from_std(left).ok()?,
+            })
+        })
+        .collect();
+    // Write to a temporary file first so a crash can't leave the cooldowns half written.
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string(&saved)?)
+        .wrap_err_with(|| format!("couldn't write cooldown file {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .wrap_err_with(|| format!("couldn't write cooldown file {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn key(user: &str) -> (UserId, UserId) {
+        (UserId::from("1"), UserId::from(user))
+    }
+
+    #[test]
+    fn second_spin_waits_for_the_cooldown() {
+        let mut cooldowns = HashMap::new();
+        let cooldown = Duration::from_secs(30);
+        let now = Instant::now();
+        assert_eq!(start(&mut cooldowns, &key("2"), cooldown, now), None);
+        assert_eq!(
+            start(
+                &mut cooldowns,
+                &key("2"),
+                cooldown,
+                now + Duration::from_secs(10)
+            ),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(start(&mut cooldowns, &key("3"), cooldown, now), None);
+        assert_eq!(
+            start(&mut cooldowns, &key("2"), cooldown, now + cooldown),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn spins_sent_at_once_only_play_once() {
+        let cooldowns = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let spin = || {
+            let cooldowns = cooldowns.clone();
+            tokio::spawn(async move {
+                let mut cooldowns = cooldowns.lock().await;
+                start(
+                    &mut cooldowns,
+                    &key("2"),
+                    Duration::from_secs(30),
+                    Instant::now(),
+                )
+            })
+        };
+        let (first, second) = tokio::join!(spin(), spin());
+        let played = [first.unwrap(), second.unwrap()]
+            .iter()
+            .filter(|left| left.is_none())
+            .count();
+        assert_eq!(played, 1);
+    }
+}
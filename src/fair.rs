@@ -0,0 +1,57 @@
+use rand::RngCore;
+use ring::{digest, hmac};
+
+/// Where the randomness for spins comes from.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngSource {
+    /// The system's random number generator
+    Random,
+    /// A secret seed the bot commits to up front and reveals with `?!reveal`, so viewers can check the spins
+    ProvablyFair,
+}
+
+/// A provably fair round in one channel, from one `?!reveal` to the next.
+///
+/// Spin `n` of the round rolls the first 8 bytes of HMAC-SHA256 keyed with the seed over the text of `n`, read as
+/// a big-endian number and divided by 2^64. The player is shot if the roll is below their odds. Publishing the
+/// SHA-256 of the seed before the round means it can't be changed to pick the outcomes.
+pub struct Round {
+    seed: [u8; 32],
+    spins: u64,
+}
+
+impl Round {
+    pub fn new() -> Self {
+        let mut seed = [0; 32];
+        rand::rng().fill_bytes(&mut seed);
+        Self { seed, spins: 0 }
+    }
+
+    /// The SHA-256 of the seed in hex, posted before the round.
+    pub fn commitment(&self) -> String {
+        hex(digest::digest(&digest::SHA256, &self.seed).as_ref())
+    }
+
+    /// The seed in hex, only posted once the round is over.
+    pub fn seed(&self) -> String {
+        hex(&self.seed)
+    }
+
+    /// Spins rolled in the round so far.
+    pub fn spins(&self) -> u64 {
+        self.spins
+    }
+
+    /// Roll the next spin, returning its number and the roll in `0..1`.
+    pub fn roll(&mut self) -> (u64, f64) {
+        self.spins += 1;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.seed);
+        let tag = hmac::sign(&key, self.spins.to_string().as_bytes());
+        let bytes: [u8; 8] = tag.as_ref()[..8].try_into().expect("sha-256 is 32 bytes");
+        (self.spins, u64::from_be_bytes(bytes) as f64 / 2f64.powi(64))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,37 @@
+/// Run the `--outcome-command` for the result of a spin, without waiting for it to finish.
+///
+/// The command is run by the shell, with the outcome (`survived`, `shot`, `grazed` or `immune`) as
+/// its first argument. The outcome, channel and player are also passed as the `ROULETTE_OUTCOME`,
+/// `ROULETTE_CHANNEL` and `ROULETTE_USER` environment variables. They are never pasted into the
+/// command itself, since display names are picked by chatters.
+pub fn run(tasks: &crate::tasks::Tasks, command: &str, outcome: &str, channel: &str, user: &str) {
+    let mut process = if cfg!(target_os = "windows") {
+        let mut process = tokio::process::Command::new("cmd");
+        process.arg("/C").arg(command).arg(outcome);
+        process
+    } else {
+        let mut process = tokio::process::Command::new("sh");
+        // The argument after the command becomes `$0`, the outcome `$1`.
+        process.arg("-c").arg(command).arg("roulette").arg(outcome);
+        process
+    };
+    process
+        .env("ROULETTE_OUTCOME", outcome)
+        .env("ROULETTE_CHANNEL", channel)
+        .env("ROULETTE_USER", user)
+        .stdin(std::process::Stdio::null());
+    tasks.spawn("the outcome command", async move {
+        let mut child = match process.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::warn!("couldn't run the outcome command: {e}");
+                return;
+            }
+        };
+        match child.wait().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => tracing::warn!(%status, "the outcome command failed"),
+            Err(e) => tracing::warn!("couldn't wait for the outcome command: {e}"),
+        }
+    });
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use eyre::WrapErr;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite;
+
+use twitch_api::twitch_oauth2::UserToken;
+use twitch_api::{eventsub::Event, types};
+
+/// Twitch's IRC server, over a websocket so it goes through the same connection code as EventSub.
+const IRC_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+
+/// Reads chat over twitch's IRC server instead of EventSub, for `--transport irc`.
+///
+/// Only chat messages are read, and they're handed to the bot as the same events EventSub sends, so everything
+/// after that works the same. The bot still posts and times out through the API.
+pub struct ChatIrcClient {
+    /// The token used to log in to IRC
+    pub token: Arc<Mutex<UserToken>>,
+    /// Chats to join, by id and login since IRC only knows logins
+    pub chats: Vec<(types::UserId, types::UserName)>,
+    /// Ping the server if nothing was received for this long, and reconnect if that went unanswered too
+    pub watchdog: std::time::Duration,
+    /// Where reconnects are reported, if anywhere
+    pub monitor: Option<crate::webhook::Webhook>,
+    /// Beats for everything received from twitch, if there's a `--heartbeat-webhook`
+    pub heartbeat: Option<Arc<crate::webhook::Heartbeat>>,
+    /// Reconnects since the bot started, shared with the bot for `?!diag`
+    pub reconnects: Arc<std::sync::atomic::AtomicU64>,
+    /// Http proxy to connect through, if any
+    pub proxy: Option<url::Url>,
+}
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+impl ChatIrcClient {
+    /// Connect to IRC, log in and join the chats.
+    async fn connect(&self) -> Result<Socket, eyre::Report> {
+        tracing::info!("connecting to twitch irc");
+        let url: url::Url = IRC_URL.parse()?;
+        let mut socket = match &self.proxy {
+            Some(proxy) => {
+                let stream = crate::websocket::tunnel(proxy, &url)
+                    .await
+                    .wrap_err_with(|| format!("Can't connect through proxy {proxy}"))?;
+                tokio_tungstenite::client_async_tls(url.as_str(), stream)
+                    .await?
+                    .0
+            }
+            None => tokio_tungstenite::connect_async(url.as_str()).await?.0,
+        };
+        let (pass, nick) = {
+            let token = self.token.lock().await;
+            (
+                format!("PASS oauth:{}", token.access_token.secret()),
+                format!("NICK {}", token.login),
+            )
+        };
+        let join = format!(
+            "JOIN {}",
+            self.chats
+                .iter()
+                .map(|(_, login)| format!("#{login}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        for line in [
+            "CAP REQ :twitch.tv/tags twitch.tv/commands".to_owned(),
+            pass,
+            nick,
+            join,
+        ] {
+            socket.send(tungstenite::Message::text(line)).await?;
+        }
+        Ok(socket)
+    }
+
+    /// Run the connection, handing each chat message to `event_fn`.
+    ///
+    /// A lost connection is reestablished, so this only returns with an error, e.g. after twitch rejected the
+    /// login or connecting again failed.
+    #[tracing::instrument(name = "irc", skip_all, fields())]
+    pub async fn run<Fut>(
+        self,
+        mut event_fn: impl FnMut(Event, types::Timestamp) -> Fut,
+    ) -> Result<(), eyre::Report>
+    where
+        Fut: std::future::Future<Output = Result<(), eyre::Report>>,
+    {
+        let mut socket = self
+            .connect()
+            .await
+            .context("when establishing connection")?;
+        let bot = self.token.lock().await.user_id.clone();
+        // IRC only pings every few minutes, so a quiet chat gets a ping of ours before it counts as stuck.
+        let mut pinged = false;
+        loop {
+            let message = match tokio::time::timeout(self.watchdog, socket.next()).await {
+                Ok(Some(Ok(message))) if !message.is_close() => message,
+                // IRC is the fallback for when EventSub has trouble, so it doesn't give up on a lost connection.
+                Ok(Some(Ok(_)) | None) => {
+                    tracing::warn!("twitch irc closed the connection, reestablishing it");
+                    socket = self.reconnect("twitch irc closed the connection").await?;
+                    pinged = false;
+                    continue;
+                }
+                Ok(Some(Err(e))) => {
+                    tracing::warn!(
+                        "couldn't read from twitch irc, reestablishing the connection: {e}"
+                    );
+                    socket = self.reconnect("couldn't read from twitch irc").await?;
+                    pinged = false;
+                    continue;
+                }
+                Err(_) if !pinged => {
+                    socket
+                        .send(tungstenite::Message::text("PING :roulette"))
+                        .await?;
+                    pinged = true;
+                    continue;
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        "nothing received from twitch irc for {}s, reestablishing the connection",
+                        self.watchdog.as_secs() * 2
+                    );
+                    socket = self.reconnect("nothing received from twitch irc").await?;
+                    pinged = false;
+                    continue;
+                }
+            };
+            pinged = false;
+            let tungstenite::Message::Text(text) = message else {
+                continue;
+            };
+            let mut reconnect = false;
+            for line in text.lines() {
+                tracing::trace!("{line}");
+                let line = Line::parse(line);
+                match line.command {
+                    "PING" => {
+                        socket
+                            .send(tungstenite::Message::text(format!(
+                                "PONG :{}",
+                                line.trailing
+                            )))
+                            .await?;
+                    }
+                    "RECONNECT" => reconnect = true,
+                    "NOTICE" if line.trailing.contains("Login authentication failed") => {
+                        eyre::bail!(
+                            "twitch irc rejected the login, the token needs the chat:read scope"
+                        );
+                    }
+                    "001" => tracing::info!("connected to twitch chat over irc"),
+                    "PRIVMSG" => {
+                        let Some((id, login)) = self.chats.iter().find(|(_, login)| {
+                            line.params.first() == Some(&format!("#{login}").as_str())
+                        }) else {
+                            continue;
+                        };
+                        match line.event(id, login, &bot) {
+                            Ok((event, timestamp)) => event_fn(event, timestamp).await?,
+                            Err(e) => {
+                                tracing::warn!("skipping irc message that couldn't be read: {e}")
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            if reconnect {
+                tracing::warn!("twitch irc asked the bot to reconnect");
+                socket = self.reconnect("twitch asked the bot to reconnect").await?;
+            }
+        }
+    }
+
+    /// Connect again after the connection was lost for `reason`, counting it as a reconnect.
+    async fn reconnect(&self, reason: &str) -> Result<Socket, eyre::Report> {
+        self.report_reconnect(reason);
+        self.connect()
+            .await
+            .context("when reestablishing connection")
+    }
+
+    /// Count a reconnect and post it to the `--monitoring-webhook`.
+    fn report_reconnect(&self, reason: &str) {
+        let attempt = self
+            .reconnects
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        if let Some(monitor) = &self.monitor {
+            monitor.post(&crate::webhook::MonitorEvent {
+                kind: "reconnect",
+                reason,
+                attempt,
+            });
+        }
+    }
+}
+
+/// One line from the IRC server, like `@tags :prefix COMMAND params :trailing`.
+struct Line<'a> {
+    tags: HashMap<&'a str, String>,
+    prefix: &'a str,
+    command: &'a str,
+    params: Vec<&'a str>,
+    trailing: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn parse(mut line: &'a str) -> Self {
+        let mut tags = HashMap::new();
+        if let Some(rest) = line.strip_prefix('@') {
+            let (raw, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+            for tag in raw.split(';') {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                tags.insert(key, unescape(value));
+            }
+            line = rest;
+        }
+        let mut prefix = "";
+        if let Some(rest) = line.strip_prefix(':') {
+            (prefix, line) = rest.split_once(' ').unwrap_or((rest, ""));
+        }
+        let (line, trailing) = line.split_once(" :").unwrap_or((line, ""));
+        let mut words = line.split(' ').filter(|w| !w.is_empty());
+        let command = words.next().unwrap_or_default();
+        Self {
+            tags,
+            prefix,
+            command,
+            params: words.collect(),
+            trailing,
+        }
+    }
+
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The message a `PRIVMSG` replies to, if it's a reply.
+    fn reply(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "parent_message_id": self.tag("reply-parent-msg-id")?,
+            "parent_message_body": self.tag("reply-parent-msg-body").unwrap_or_default(),
+            "parent_user_id": self.tag("reply-parent-user-id").unwrap_or_default(),
+            "parent_user_name": self.tag("reply-parent-display-name").unwrap_or_default(),
+            "parent_user_login": self.tag("reply-parent-user-login").unwrap_or_default(),
+            "thread_message_id": self.tag("reply-thread-parent-msg-id").unwrap_or_default(),
+            "thread_user_id": self.tag("reply-thread-parent-user-id").unwrap_or_default(),
+            "thread_user_name": self.tag("reply-thread-parent-display-name").unwrap_or_default(),
+            "thread_user_login": self.tag("reply-thread-parent-user-login").unwrap_or_default(),
+        }))
+    }
+
+    /// Turn a `PRIVMSG` into the event EventSub would have sent for it.
+    fn event(
+        &self,
+        broadcaster: &types::UserId,
+        broadcaster_login: &types::UserName,
+        bot: &types::UserId,
+    ) -> Result<(Event, types::Timestamp), eyre::Report> {
+        let login = self.prefix.split('!').next().unwrap_or_default();
+        let months: HashMap<&str, &str> = self
+            .tag("badge-info")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|badge| badge.split_once('/'))
+            .collect();
+        let badges: Vec<_> = self
+            .tag("badges")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|badge| badge.split_once('/'))
+            .map(|(set_id, id)| {
+                serde_json::json!({
+                    "set_id": set_id,
+                    "id": id,
+                    "info": months.get(set_id).copied().unwrap_or_default(),
+                })
+            })
+            .collect();
+        let sent = self
+            .tag("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .unwrap_or_else(chrono::Utc::now)
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let event = serde_json::json!({
+            "subscription": {
+                "id": "irc",
+                "status": "enabled",
+                "type": "channel.chat.message",
+                "version": "1",
+                "condition": {
+                    "broadcaster_user_id": broadcaster,
+                    "user_id": bot,
+                },
+                "transport": {"method": "websocket", "session_id": "irc"},
+                "created_at": sent,
+                "cost": 0,
+            },
+            "event": {
+                "broadcaster_user_id": broadcaster,
+                "broadcaster_user_login": broadcaster_login,
+                "broadcaster_user_name": broadcaster_login,
+                "chatter_user_id": self.tag("user-id").unwrap_or_default(),
+                "chatter_user_login": login,
+                "chatter_user_name": self.tag("display-name").unwrap_or(login),
+                "message_id": self.tag("id").unwrap_or_default(),
+                "message": {
+                    "text": self.trailing,
+                    "fragments": [{
+                        "type": "text",
+                        "text": self.trailing,
+                        "cheermote": null,
+                        "emote": null,
+                        "mention": null,
+                    }],
+                },
+                "color": self.tag("color").unwrap_or_default(),
+                "badges": badges,
+                "message_type": "text",
+                "cheer": self
+                    .tag("bits")
+                    .and_then(|bits| bits.parse::<usize>().ok())
+                    .map(|bits| serde_json::json!({"bits": bits})),
+                "reply": self.reply(),
+                "channel_points_custom_reward_id": self.tag("custom-reward-id"),
+                "source_broadcaster_user_id": self.tag("source-room-id"),
+                "source_broadcaster_user_login": null,
+                "source_broadcaster_user_name": null,
+                "source_message_id": self.tag("source-id"),
+                "source_badges": null,
+            },
+        });
+        Ok((
+            Event::parse(&event.to_string())?,
+            types::Timestamp::new(sent)?,
+        ))
+    }
+}
+
+/// Undo the escaping of IRC tag values.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some(':') => unescaped.push(';'),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
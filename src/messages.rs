@@ -0,0 +1,223 @@
+use std::fmt::Display;
+
+/// Fill in the `{name}` placeholders of a message template.
+///
+/// Placeholders without a value are left as they are, so a typo in a template shows up in chat
+/// instead of silently disappearing.
+pub fn render(template: &str, vars: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Write a duration the short way for chat, like `3h12m`, `5m` or `42s`.
+pub fn short_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs % 3600 / 60) {
+        (0, 0) => format!("{secs}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h{minutes}m"),
+    }
+}
+
+/// Cut a message to at most `max` characters, ending it with an ellipsis if anything was cut.
+pub fn truncate(mut message: String, max: u64) -> String {
+    let max = usize::try_from(max).unwrap_or(usize::MAX);
+    if let Some((end, _)) = message.char_indices().nth(max) {
+        // Leave room for the ellipsis.
+        let end = message[..end]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(last, _)| last);
+        message.truncate(end);
+        message.push('…');
+    }
+    message
+}
+
+/// Join names with commas in at most `max` characters, summing up the ones that don't fit like `and 3 more`.
+pub fn list(names: &[&str], max: u64) -> String {
+    let mut list = String::new();
+    for (i, name) in names.iter().enumerate() {
+        let next = if i == 0 {
+            (*name).to_owned()
+        } else {
+            format!(", {name}")
+        };
+        let after = names.len() - i - 1;
+        let summary = if after == 0 {
+            0
+        } else {
+            format!(" and {after} more").chars().count()
+        };
+        if (list.chars().count() + next.chars().count() + summary) as u64 > max {
+            let left = names.len() - i;
+            if i == 0 {
+                return format!("{left} players");
+            }
+            list.push_str(&format!(" and {left} more"));
+            return list;
+        }
+        list.push_str(&next);
+    }
+    list
+}
+
+/// The outcome messages `--translation` can translate, by the option that sets them.
+pub const TRANSLATABLE: &[&str] = &[
+    "survive_message",
+    "pity_message",
+    "shot_message",
+    "death_record_message",
+    "graze_message",
+    "immune_message",
+    "protected_message",
+    "broadcaster_message",
+];
+
+/// An outcome message in another language, for players who picked it with `?!lang`, written as `lang key=text`
+/// like `es shot_message=¡{user} recibió un disparo!`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    pub language: String,
+    /// One of [`TRANSLATABLE`]
+    pub key: &'static str,
+    pub text: String,
+}
+
+impl std::str::FromStr for Translation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (language, rest) = s.trim_start().split_once(' ').ok_or_else(|| {
+            format!("`{s}` is not a language and message like es shot_message=...")
+        })?;
+        let (key, text) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("`{rest}` is not a message like shot_message=..."))?;
+        let key = key.trim().replace('-', "_");
+        let key = TRANSLATABLE.iter().find(|k| **k == key).ok_or_else(|| {
+            format!(
+                "`{key}` can't be translated, only {} can",
+                TRANSLATABLE.join(", ")
+            )
+        })?;
+        Ok(Self {
+            language: language.to_lowercase(),
+            key,
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// How numbers are written in messages, for `--number-format`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// 1234.5
+    #[default]
+    Plain,
+    /// 1,234.5
+    En,
+    /// 1.234,5
+    De,
+    /// 1 234,5
+    Fr,
+    /// 1'234.5
+    Ch,
+}
+
+impl NumberFormat {
+    /// The thousands separator and decimal mark, `None` for plain numbers.
+    fn separators(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            NumberFormat::Plain => None,
+            NumberFormat::En => Some((",", ".")),
+            NumberFormat::De => Some((".", ",")),
+            // A narrow no-break space, so numbers don't get wrapped in the middle.
+            NumberFormat::Fr => Some(("\u{202f}", ",")),
+            NumberFormat::Ch => Some(("'", ".")),
+        }
+    }
+
+    /// Write a number, e.g. `1234.5` as `1.234,5` for [`NumberFormat::De`].
+    pub fn format(self, number: impl Display) -> String {
+        let number = number.to_string();
+        let Some((thousands, decimal)) = self.separators() else {
+            return number;
+        };
+        let (sign, unsigned) = match number.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", number.as_str()),
+        };
+        let (int, fraction) = match unsigned.split_once('.') {
+            Some((int, fraction)) => (int, Some(fraction)),
+            None => (unsigned, None),
+        };
+        if !int.bytes().all(|b| b.is_ascii_digit()) {
+            // Not a plain number, e.g. `inf`.
+            return number;
+        }
+        let mut out = sign.to_owned();
+        for (i, digit) in int.chars().enumerate() {
+            if i > 0 && (int.len() - i) % 3 == 0 {
+                out.push_str(thousands);
+            }
+            out.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            out.push_str(decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_messages_fit_in_chat() {
+        let names: Vec<String> = (0..200).map(|i| format!("chatter{i}")).collect();
+        let message = format!("Most shots: {}", names.join(", "));
+        assert!(message.chars().count() > 500);
+        let cut = truncate(message.clone(), 500);
+        assert_eq!(cut.chars().count(), 500);
+        assert!(cut.ends_with('…'));
+        assert!(message.starts_with(cut.trim_end_matches('…')));
+    }
+
+    #[test]
+    fn truncating_keeps_whole_characters() {
+        let cut = truncate("ÄÖÜ🎲🎲🎲".repeat(100), 500);
+        assert_eq!(cut.chars().count(), 500);
+        assert!(cut.ends_with('…'));
+    }
+
+    #[test]
+    fn short_messages_are_left_alone() {
+        let message = "x".repeat(500);
+        assert_eq!(truncate(message.clone(), 500), message);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use eyre::WrapErr;
+use tokio::sync::Mutex;
+
+/// Counters served on `--metrics-addr`, in the OpenMetrics text format.
+///
+/// Counters are labeled by channel and by things with a fixed set of values, like the outcome of a spin, but
+/// never by user: the bot would report a new series for everyone who ever played. The channels are the ones in
+/// `--broadcaster-login`, so there's only ever a handful.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Spins by channel and outcome
+    spins: Mutex<BTreeMap<(String, &'static str), u64>>,
+    /// Commands used, by channel and command
+    commands: Mutex<BTreeMap<(String, &'static str), u64>>,
+    /// Events from twitch, shared with the bot for `?!diag`
+    events: Arc<AtomicU64>,
+    /// Reconnects to twitch, shared with the connection
+    reconnects: Arc<AtomicU64>,
+    /// Background tasks running or waiting for a slot, shared with the tasks
+    tasks: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    pub fn new(events: Arc<AtomicU64>, reconnects: Arc<AtomicU64>, tasks: Arc<AtomicU64>) -> Self {
+        Self {
+            events,
+            reconnects,
+            tasks,
+            ..Default::default()
+        }
+    }
+
+    /// Count a spin in `channel` that ended in `outcome`, like `"shot"`.
+    pub async fn spin(&self, channel: &str, outcome: &'static str) {
+        *self
+            .spins
+            .lock()
+            .await
+            .entry((channel.to_owned(), outcome))
+            .or_default() += 1;
+    }
+
+    /// Count a use of `command` in `channel`, by its key.
+    pub async fn command(&self, channel: &str, command: &'static str) {
+        *self
+            .commands
+            .lock()
+            .await
+            .entry((channel.to_owned(), command))
+            .or_default() += 1;
+    }
+
+    async fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "roulette_spins",
+            "Spins of the revolver.",
+            self.spins
+                .lock()
+                .await
+                .iter()
+                .map(|((channel, outcome), n)| {
+                    (
+                        format!("channel=\"{}\",outcome=\"{outcome}\"", escape(channel)),
+                        *n,
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "roulette_commands",
+            "Commands the bot answered.",
+            self.commands
+                .lock()
+                .await
+                .iter()
+                .map(|((channel, command), n)| {
+                    (
+                        format!("channel=\"{}\",command=\"{command}\"", escape(channel)),
+                        *n,
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "roulette_events",
+            "Events received from twitch.",
+            [(String::new(), self.events.load(Ordering::Relaxed))],
+        );
+        counter(
+            &mut out,
+            "roulette_reconnects",
+            "Reconnects to twitch.",
+            [(String::new(), self.reconnects.load(Ordering::Relaxed))],
+        );
+        let _ = writeln!(out, "# TYPE roulette_tasks gauge");
+        let _ = writeln!(
+            out,
+            "# HELP roulette_tasks Background tasks running or waiting for a slot."
+        );
+        let _ = writeln!(out, "roulette_tasks {}", self.tasks.load(Ordering::Relaxed));
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Write a counter family with a sample for each set of labels.
+fn counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, u64)>,
+) {
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "# HELP {name} {help}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name}_total {value}");
+        } else {
+            let _ = writeln!(out, "{name}_total{{{labels}}} {value}");
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Serve the metrics on `/metrics` until the bot stops.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<(), eyre::Report> {
+    let app = axum::Router::new()
+        .route(
+            "/metrics",
+            axum::routing::get(
+                |axum::extract::State(metrics): axum::extract::State<Arc<Metrics>>| async move {
+                    (
+                        [(
+                            http::header::CONTENT_TYPE,
+                            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                        )],
+                        metrics.render().await,
+                    )
+                },
+            ),
+        )
+        .with_state(metrics);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("couldn't serve metrics on {addr}"))?;
+    tracing::info!(%addr, "serving metrics");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
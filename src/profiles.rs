@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use twitch_api::types::UserName;
+
+use crate::commands::Command;
+use crate::messages::TRANSLATABLE;
+
+/// Words `?!profile` takes that can't be the name of a profile.
+const RESERVED: &[&str] = &["off", "default"];
+
+/// One setting of a named channel profile, written as `name key=value` like `hardcore bullets=3` or
+/// `chill shot_message={user} got hit by a foam dart`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    pub profile: String,
+    pub value: Value,
+}
+
+/// What a channel profile can set, each replacing the option of the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bullets(u32),
+    Chambers(u32),
+    Timeout(u32),
+    Cooldown(u64),
+    DisabledCommands(Vec<Command>),
+    /// One of the outcome messages in [`TRANSLATABLE`]
+    Message(&'static str, String),
+}
+
+impl std::str::FromStr for Setting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (profile, rest) = s
+            .trim_start()
+            .split_once(' ')
+            .ok_or_else(|| format!("`{s}` is not a profile and setting like hardcore bullets=3"))?;
+        if RESERVED.contains(&profile) {
+            return Err(format!("a profile can't be called `{profile}`"));
+        }
+        let (key, value) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("`{rest}` is not a setting like bullets=3"))?;
+        let key = key.trim().replace('-', "_");
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value > 0)
+                .ok_or_else(|| {
+                    format!(
+                        "`{value}` for {key} in the profile {profile} has to be a number above 0"
+                    )
+                })
+        };
+        let value = match key.as_str() {
+            "bullets" => Value::Bullets(number(value)?),
+            "chambers" => Value::Chambers(number(value)?),
+            "timeout" => Value::Timeout(number(value)?.min(1_209_600)),
+            "cooldown" => Value::Cooldown(value.trim().parse().map_err(|_| {
+                format!("`{value}` for cooldown in the profile {profile} is not a number of seconds")
+            })?),
+            "disabled_commands" => Value::DisabledCommands(
+                value
+                    .split(',')
+                    .filter(|command| !command.trim().is_empty())
+                    .map(|command| Command::from_key(command.trim()))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => match TRANSLATABLE.iter().find(|k| **k == key) {
+                Some(key) => Value::Message(key, value.to_owned()),
+                None => {
+                    return Err(format!(
+                        "the profile {profile} can't set `{key}`, only bullets, chambers, timeout, cooldown, disabled_commands and {}",
+                        TRANSLATABLE.join(", ")
+                    ))
+                }
+            },
+        };
+        Ok(Self {
+            profile: profile.to_owned(),
+            value,
+        })
+    }
+}
+
+/// A channel profile put together from its settings. Whatever it doesn't set comes from the usual options.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelProfile {
+    pub bullets: Option<u32>,
+    pub chambers: Option<u32>,
+    pub timeout: Option<u32>,
+    pub cooldown: Option<u64>,
+    /// Commands that are off in the channel unless a mod turns them on, in place of `--disabled-commands`
+    pub disabled_commands: Option<Vec<Command>>,
+    /// Outcome messages by key
+    pub messages: BTreeMap<&'static str, String>,
+}
+
+/// Put the `--channel-profile` settings together into profiles by name, later settings winning.
+pub fn collect(settings: &[Setting]) -> BTreeMap<String, ChannelProfile> {
+    let mut profiles: BTreeMap<String, ChannelProfile> = BTreeMap::new();
+    for setting in settings {
+        let profile = profiles.entry(setting.profile.clone()).or_default();
+        match &setting.value {
+            Value::Bullets(bullets) => profile.bullets = Some(*bullets),
+            Value::Chambers(chambers) => profile.chambers = Some(*chambers),
+            Value::Timeout(timeout) => profile.timeout = Some(*timeout),
+            Value::Cooldown(cooldown) => profile.cooldown = Some(*cooldown),
+            Value::DisabledCommands(commands) => profile.disabled_commands = Some(commands.clone()),
+            Value::Message(key, text) => {
+                profile.messages.insert(key, text.clone());
+            }
+        }
+    }
+    profiles
+}
+
+/// The profile a channel starts with until a mod picks another, written as `login=profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub login: UserName,
+    pub profile: String,
+}
+
+impl std::str::FromStr for Assignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (login, profile) = s
+            .split_once('=')
+            .ok_or_else(|| format!("`{s}` is not a channel and profile like alice=hardcore"))?;
+        Ok(Self {
+            login: login.trim().to_lowercase().into(),
+            profile: profile.trim().to_owned(),
+        })
+    }
+}
+
+/// The profile a mod picked for a channel with `?!profile`, kept in the stats so it survives a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Choice {
+    /// No profile, even if the channel has an `--assign-profile`
+    Off,
+    Named(String),
+}
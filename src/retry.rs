@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use twitch_api::helix::{
+    ClientRequestError, HelixRequestDeleteError, HelixRequestGetError, HelixRequestPatchError,
+    HelixRequestPostError, HelixRequestPutError,
+};
+
+/// Tries at a helix request before a server error is given up on.
+const ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for every retry after it.
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Send a helix request, trying again with backoff if twitch answers with a server error.
+///
+/// Server errors are twitch's own hiccups and usually gone a moment later. Anything else, like a 4xx for a
+/// missing scope or a user that doesn't exist, is returned right away since retrying won't change it. With
+/// chat messages a retry can post a message twice if twitch sent it and failed after, which beats losing it.
+///
+/// If twitch is still failing after the last try the error is returned like any other, and the event that needed
+/// the request is given up on without stopping the bot.
+pub async fn helix<T, RE, Fut>(
+    what: &str,
+    mut request: impl FnMut() -> Fut,
+) -> Result<T, ClientRequestError<RE>>
+where
+    RE: std::error::Error + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<T, ClientRequestError<RE>>>,
+{
+    let mut backoff = BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if attempt < ATTEMPTS && is_server_error(&e) => {
+                tracing::warn!(
+                    attempt,
+                    "twitch had trouble with {what}, trying again in {}ms: {e}",
+                    backoff.as_millis()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) if is_server_error(&e) => {
+                tracing::error!(attempt, "twitch kept failing {what}, giving up: {e}");
+                return Err(e);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether twitch failed the request on its end with a 5xx.
+pub fn is_server_error<RE: std::error::Error + Send + Sync + 'static>(
+    error: &ClientRequestError<RE>,
+) -> bool {
+    status(error).is_some_and(|status| status.is_server_error())
+}
+
+/// The status twitch answered a failed request with, if it got that far.
+///
+/// Server errors don't always come with a json body, so answers that couldn't be read count too.
+fn status<RE: std::error::Error + Send + Sync + 'static>(
+    error: &ClientRequestError<RE>,
+) -> Option<http::StatusCode> {
+    match error {
+        ClientRequestError::HelixRequestGetError(
+            HelixRequestGetError::Error { status, .. }
+            | HelixRequestGetError::DeserializeError(_, _, _, status)
+            | HelixRequestGetError::InvalidResponse { status, .. },
+        )
+        | ClientRequestError::HelixRequestPutError(
+            HelixRequestPutError::Error { status, .. }
+            | HelixRequestPutError::DeserializeError(_, _, _, status)
+            | HelixRequestPutError::InvalidResponse { status, .. },
+        )
+        | ClientRequestError::HelixRequestPostError(
+            HelixRequestPostError::Error { status, .. }
+            | HelixRequestPostError::DeserializeError(_, _, _, status)
+            | HelixRequestPostError::InvalidResponse { status, .. },
+        )
+        | ClientRequestError::HelixRequestPatchError(
+            HelixRequestPatchError::Error { status, .. }
+            | HelixRequestPatchError::DeserializeError(_, _, _, status)
+            | HelixRequestPatchError::InvalidResponse { status, .. },
+        )
+        | ClientRequestError::HelixRequestDeleteError(
+            HelixRequestDeleteError::Error { status, .. }
+            | HelixRequestDeleteError::InvalidResponse { status, .. },
+        ) => Some(*status),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn answer(status: http::StatusCode) -> ClientRequestError<std::io::Error> {
+        ClientRequestError::HelixRequestGetError(HelixRequestGetError::Error {
+            error: status.canonical_reason().unwrap_or_default().to_owned(),
+            status,
+            message: String::new(),
+            uri: http::Uri::from_static("https://api.twitch.tv/helix/users"),
+        })
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let tries = AtomicU32::new(0);
+        let result = helix("looking up a user", || async {
+            match tries.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(answer(http::StatusCode::INTERNAL_SERVER_ERROR)),
+                _ => Ok("bob"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "bob");
+        assert_eq!(tries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn client_errors_arent_retried() {
+        let tries = AtomicU32::new(0);
+        let result: Result<(), _> = helix("looking up a user", || async {
+            tries.fetch_add(1, Ordering::Relaxed);
+            Err(answer(http::StatusCode::BAD_REQUEST))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(tries.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_given_up_on() {
+        let tries = AtomicU32::new(0);
+        let result: Result<(), _> = helix("looking up a user", || async {
+            tries.fetch_add(1, Ordering::Relaxed);
+            Err(answer(http::StatusCode::SERVICE_UNAVAILABLE))
+        })
+        .await;
+        assert!(result.is_err_and(|e| is_server_error(&e)));
+        assert_eq!(tries.load(Ordering::Relaxed), ATTEMPTS);
+    }
+}
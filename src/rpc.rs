@@ -0,0 +1,347 @@
+use std::net::SocketAddr;
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use twitch_api::types::{UserId, UserName};
+
+use crate::stats::Safety;
+
+/// Calls waiting for the bot, a few is plenty since each one is quick.
+const QUEUE: usize = 16;
+
+/// A JSON-RPC 2.0 request, one per http request.
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+    id: Value,
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, Error>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+/// Why a call failed, with the codes JSON-RPC sets aside for it where there is one.
+#[derive(Debug, Serialize)]
+pub struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn parse(e: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32700,
+            message: format!("couldn't parse the request: {e}"),
+        }
+    }
+
+    fn invalid_request(message: &str) -> Self {
+        Self {
+            code: -32600,
+            message: message.to_owned(),
+        }
+    }
+
+    fn no_method(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("there's no method called `{method}`"),
+        }
+    }
+
+    fn invalid_params(e: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32602,
+            message: format!("invalid params: {e}"),
+        }
+    }
+
+    /// Something about the call the bot can't do, like a channel it isn't in.
+    fn failed(message: impl Into<String>) -> Self {
+        Self {
+            code: -32000,
+            message: message.into(),
+        }
+    }
+}
+
+/// A call handed from the http server to the bot, which answers on `reply`.
+struct Call {
+    method: String,
+    params: Value,
+    reply: oneshot::Sender<Result<Value, Error>>,
+}
+
+#[derive(Clone)]
+struct State {
+    calls: mpsc::Sender<Call>,
+    token: String,
+}
+
+/// Serve the JSON-RPC control plane on `addr` until the bot stops, for `--rpc-addr`.
+///
+/// The server only passes calls on, they're made here one at a time with the bot's own state, like chat
+/// commands are.
+pub async fn run(bot: &crate::Bot, addr: SocketAddr, token: &str) -> Result<(), eyre::Report> {
+    let (calls, mut queue) = mpsc::channel(QUEUE);
+    let app = axum::Router::new()
+        .route("/rpc", axum::routing::post(post))
+        .with_state(State {
+            calls,
+            token: token.to_owned(),
+        });
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("couldn't serve rpc on {addr}"))?;
+    tracing::info!(%addr, "serving rpc");
+    let server = async {
+        axum::serve(listener, app).await?;
+        Ok::<_, eyre::Report>(())
+    };
+    let answer = async {
+        while let Some(call) = queue.recv().await {
+            tracing::info!(method = %call.method, "rpc call");
+            let result = call_method(bot, &call.method, call.params).await;
+            let _ = call.reply.send(result);
+        }
+        Ok(())
+    };
+    tokio::try_join!(server, answer)?;
+    Ok(())
+}
+
+async fn post(
+    axum::extract::State(state): axum::extract::State<State>,
+    headers: http::HeaderMap,
+    body: axum::body::Bytes,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let authorized = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| same_token(token, &state.token));
+    if !authorized {
+        return http::StatusCode::UNAUTHORIZED.into_response();
+    }
+    let request = match serde_json::from_slice::<Request>(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return axum::Json(Response::new(Value::Null, Err(Error::parse(e)))).into_response()
+        }
+    };
+    if request.jsonrpc != "2.0" {
+        return axum::Json(Response::new(
+            request.id,
+            Err(Error::invalid_request("only JSON-RPC 2.0 is spoken here")),
+        ))
+        .into_response();
+    }
+    let (reply, answer) = oneshot::channel();
+    let call = Call {
+        method: request.method,
+        params: request.params,
+        reply,
+    };
+    let result = match state.calls.send(call).await {
+        Ok(()) => answer
+            .await
+            .unwrap_or_else(|_| Err(Error::failed("the bot is stopping"))),
+        Err(_) => Err(Error::failed("the bot is stopping")),
+    };
+    axum::Json(Response::new(request.id, result)).into_response()
+}
+
+/// Compare tokens in the same time wherever they differ, so the time taken doesn't give the token away.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+#[derive(Deserialize)]
+struct StatsParams {
+    channel: UserName,
+    user: Option<UserName>,
+}
+
+#[derive(Deserialize)]
+struct SafetyParams {
+    channel: UserName,
+    safety: Safety,
+}
+
+#[derive(Deserialize)]
+struct LuckParams {
+    channel: UserName,
+    user: UserName,
+    /// Added to the odds of getting shot, 0 to take the luck away
+    odds: f64,
+}
+
+#[derive(Deserialize)]
+struct SpinParams {
+    channel: UserName,
+    user: UserName,
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(Error::invalid_params)
+}
+
+async fn call_method(bot: &crate::Bot, method: &str, params_: Value) -> Result<Value, Error> {
+    match method {
+        "stats" => stats(bot, params(params_)?).await,
+        "safety" => safety(bot, params(params_)?).await,
+        "luck" => luck(bot, params(params_)?).await,
+        "spin" => spin(bot, params(params_)?).await,
+        _ => Err(Error::no_method(method)),
+    }
+}
+
+/// The id of one of the `--broadcaster-login` channels.
+fn channel(bot: &crate::Bot, login: &UserName) -> Result<UserId, Error> {
+    bot.opts
+        .broadcaster_login
+        .iter()
+        .position(|l| l == login)
+        .map(|i| bot.broadcasters[i].clone())
+        .ok_or_else(|| Error::failed(format!("the bot isn't in {login}'s channel")))
+}
+
+async fn user(bot: &crate::Bot, login: &UserName) -> Result<twitch_api::helix::users::User, Error> {
+    let token = bot.token.lock().await.clone();
+    crate::retry::helix("looking up a user", || {
+        bot.client.get_user_from_login(login, &token)
+    })
+    .await
+    .map_err(|e| Error::failed(format!("couldn't look up {login}: {e}")))?
+    .ok_or_else(|| Error::failed(format!("there's nobody called {login}")))
+}
+
+/// A player's stats in a channel, or the channel's totals without a user.
+async fn stats(bot: &crate::Bot, params: StatsParams) -> Result<Value, Error> {
+    let broadcaster = channel(bot, &params.channel)?;
+    let user = match &params.user {
+        Some(login) => Some(user(bot, login).await?),
+        None => None,
+    };
+    let mut stats = bot.stats.lock().await;
+    let value = match user {
+        Some(user) => serde_json::to_value(stats.user_stats(&broadcaster, &user.id)),
+        None => serde_json::to_value(stats.totals(&broadcaster)),
+    };
+    value.map_err(|e| Error::failed(e.to_string()))
+}
+
+/// Open or close roulette in a channel, like `?!safety`.
+async fn safety(bot: &crate::Bot, params: SafetyParams) -> Result<Value, Error> {
+    let broadcaster = channel(bot, &params.channel)?;
+    {
+        let mut stats = bot.stats.lock().await;
+        stats.set_safety(&broadcaster, &params.channel, params.safety);
+        if let Err(e) = stats.save() {
+            tracing::error!("{e:?}");
+        }
+    }
+    tracing::info!(channel = %params.channel, safety = ?params.safety, "safety changed over rpc");
+    Ok(serde_json::json!({ "safety": params.safety }))
+}
+
+/// Bless or curse a player's next spins like `?!luck`, but by any amount up to `--luck-max`.
+async fn luck(bot: &crate::Bot, params: LuckParams) -> Result<Value, Error> {
+    let broadcaster = channel(bot, &params.channel)?;
+    if !params.odds.is_finite() {
+        return Err(Error::invalid_params("odds has to be a number"));
+    }
+    let user = user(bot, &params.user).await?;
+    let odds = params.odds.clamp(-bot.opts.luck_max, bot.opts.luck_max);
+    let key = (broadcaster, user.id);
+    {
+        let mut luck = bot.luck.lock().await;
+        if odds.abs() < 1e-9 {
+            luck.remove(&key);
+        } else {
+            luck.insert(
+                key,
+                crate::Luck {
+                    odds,
+                    spins: bot.opts.luck_spins,
+                    until: std::time::Instant::now()
+                        + std::time::Duration::from_secs(bot.opts.luck_minutes * 60),
+                },
+            );
+        }
+    }
+    tracing::info!(channel = %params.channel, user = %params.user, odds, "luck changed over rpc");
+    Ok(serde_json::json!({ "odds": odds }))
+}
+
+/// Spin the revolver for a player, as if they used the roulette command in chat. The outcome is posted there.
+async fn spin(bot: &crate::Bot, params: SpinParams) -> Result<Value, Error> {
+    let broadcaster = channel(bot, &params.channel)?;
+    let user = user(bot, &params.user).await?;
+    let token = bot.token.lock().await.clone();
+    let (payload, subscription) = crate::made_up_message(
+        "rpc",
+        serde_json::json!({
+            "broadcaster_user_id": broadcaster,
+            "broadcaster_user_login": params.channel,
+            "broadcaster_user_name": params.channel,
+            "chatter_user_id": user.id,
+            "chatter_user_login": user.login,
+            "chatter_user_name": user.display_name,
+        }),
+        &format!(
+            "{}{}",
+            bot.command_prefix(&broadcaster),
+            bot.opts.command_name
+        ),
+        &token.user_id,
+    )
+    .map_err(|e| Error::failed(e.to_string()))?;
+    if bot.is_ignored(&payload).await {
+        return Err(Error::failed(format!("{} is ignored", params.user)));
+    }
+    bot.command(
+        &payload,
+        &subscription,
+        &bot.opts.command_name,
+        None,
+        &token,
+    )
+    .await
+    .map_err(|e| Error::failed(format!("{e:#}")))?;
+    Ok(Value::Null)
+}
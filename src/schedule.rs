@@ -0,0 +1,162 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+
+/// A daily time range like `23:00-08:00`, which may wrap around midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    /// Whether `time` falls within the range, the end being exclusive.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("`{s}` is not a time range like 23:00-08:00"))?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|e| format!("`{t}` is not a time like 23:00: {e}"))
+        };
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl std::fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Odds and timeouts that replace the usual ones at certain times, written like
+/// `weekend sat,sun bullets=2 timeout=300` or `late mon-fri 23:00-02:00 chambers=3`.
+///
+/// Days can be listed or given as a range, or be `daily`. Without a time range the profile lasts the whole day.
+/// A time range that wraps around midnight belongs to the day it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub days: Vec<Weekday>,
+    pub hours: Option<TimeRange>,
+    pub bullets: Option<u32>,
+    pub chambers: Option<u32>,
+    pub timeout: Option<u32>,
+}
+
+impl Profile {
+    /// Whether the profile is on at `now`, in the `--timezone`.
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+        let day = now.weekday();
+        match self.hours {
+            None => self.days.contains(&day),
+            Some(hours) if !hours.contains(time) => false,
+            // Past midnight, so the range started the day before.
+            Some(hours) if hours.start > hours.end && time < hours.end => {
+                self.days.contains(&day.pred())
+            }
+            Some(_) => self.days.contains(&day),
+        }
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let (Some(name), Some(days)) = (words.next(), words.next()) else {
+            return Err(format!(
+                "`{s}` is not a profile like `weekend sat,sun bullets=2 timeout=300`"
+            ));
+        };
+        let mut profile = Self {
+            name: name.to_owned(),
+            days: parse_days(days)?,
+            hours: None,
+            bullets: None,
+            chambers: None,
+            timeout: None,
+        };
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                if profile.hours.is_some() {
+                    return Err(format!("the profile {name} has more than one time range"));
+                }
+                profile.hours = Some(word.parse()?);
+                continue;
+            };
+            let value: u32 = value
+                .parse()
+                .ok()
+                .filter(|value| *value > 0)
+                .ok_or_else(|| {
+                    format!("`{value}` for {key} in the profile {name} has to be a number above 0")
+                })?;
+            match key {
+                "bullets" => profile.bullets = Some(value),
+                "chambers" => profile.chambers = Some(value),
+                "timeout" => profile.timeout = Some(value.min(1_209_600)),
+                _ => {
+                    return Err(format!(
+                        "the profile {name} can't set `{key}`, only bullets, chambers and timeout"
+                    ))
+                }
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// Days like `sat,sun`, `mon-fri` or `daily`.
+fn parse_days(s: &str) -> Result<Vec<Weekday>, String> {
+    if s == "daily" {
+        return Ok(vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ]);
+    }
+    let day = |d: &str| {
+        d.parse::<Weekday>()
+            .map_err(|_| format!("`{d}` is not a day like mon or sat"))
+    };
+    let mut days = vec![];
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (mut d, last) = (day(first)?, day(last)?);
+                days.push(d);
+                while d != last {
+                    d = d.succ();
+                    days.push(d);
+                }
+            }
+            None => days.push(day(part)?),
+        }
+    }
+    Ok(days)
+}
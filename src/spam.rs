@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often a user used commands recently, to stop answering someone who floods chat with them.
+#[derive(Debug, Default)]
+pub struct CommandRate {
+    /// When the commands within the window were used
+    used: VecDeque<Instant>,
+    /// Until when the user is ignored
+    muted_until: Option<Instant>,
+}
+
+/// Whether a command may be answered, see [`CommandRate::hit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// The user went over the limit with this command
+    Muted,
+    /// The user is still muted from earlier
+    StillMuted,
+}
+
+impl CommandRate {
+    /// Count a command, muting the user for `mute` if they used more than `limit` within `window`.
+    pub fn hit(&mut self, limit: usize, window: Duration, mute: Duration) -> Verdict {
+        let now = Instant::now();
+        if self.muted_until.is_some_and(|until| now < until) {
+            return Verdict::StillMuted;
+        }
+        self.muted_until = None;
+        while self
+            .used
+            .front()
+            .is_some_and(|used| now.duration_since(*used) >= window)
+        {
+            self.used.pop_front();
+        }
+        self.used.push_back(now);
+        if self.used.len() > limit {
+            self.used.clear();
+            self.muted_until = Some(now + mute);
+            return Verdict::Muted;
+        }
+        Verdict::Allowed
+    }
+
+    /// Whether nothing about the user needs to be remembered anymore.
+    pub fn is_idle(&self, window: Duration) -> bool {
+        let now = Instant::now();
+        self.muted_until.is_none_or(|until| now >= until)
+            && self
+                .used
+                .back()
+                .is_none_or(|used| now.duration_since(*used) >= window)
+    }
+}
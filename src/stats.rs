@@ -0,0 +1,1070 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use twitch_api::types::{UserId, UserName};
+
+/// What `?!rank` ranks players by.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankBy {
+    /// The most survivals first, fewer deaths breaking ties like `?!top`
+    Survivals,
+    /// The most deaths first
+    Deaths,
+}
+
+/// Version of the stats file written by this build, bumped whenever an older file needs a [migration](migrate).
+const VERSION: u64 = 2;
+
+/// Roulette stats of every channel, persisted as json.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Version of the file format, files from before there was one are version 1
+    #[serde(default)]
+    pub version: u64,
+    /// Stats per channel, keyed by broadcaster id
+    pub channels: BTreeMap<UserId, ChannelStats>,
+    /// Where the stats are saved
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// When changes are written, with `--stats-flush-interval`
+    #[serde(skip)]
+    batching: Batching,
+}
+
+/// Changes to the stats kept in memory to be written together, rather than the whole file for each one.
+#[derive(Debug, Default)]
+struct Batching {
+    /// Longest a change waits to be written, zero to write every change right away
+    interval: std::time::Duration,
+    /// Changes that get written right away once this many are waiting
+    changes: u64,
+    /// Changes that haven't been written yet
+    pending: u64,
+    /// When the stats were last written
+    written: Option<std::time::Instant>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelStats {
+    /// Login of the broadcaster, so the channel can be found without asking twitch
+    pub login: UserName,
+    /// Stats per user, keyed by user id
+    pub users: BTreeMap<UserId, UserStats>,
+    /// Set by mods with `?!safety`
+    #[serde(default)]
+    pub safety: Safety,
+    /// Commands turned on or off by mods with `?!enable` and `?!disable`, keyed by command key
+    #[serde(default)]
+    pub commands: BTreeMap<String, bool>,
+    /// Logins mods told the bot to ignore with `?!ignore`
+    #[serde(default)]
+    pub ignored: BTreeSet<String>,
+    /// Users mods made immune to roulette with `?!protect`
+    #[serde(default)]
+    pub protected: BTreeSet<UserId>,
+    /// Users the bot made VIP for `--vip-streak`, the only VIPs it takes away again
+    #[serde(default)]
+    pub vips: BTreeSet<UserId>,
+    /// Alts mods linked with `?!link`, to the account they share a cooldown with
+    #[serde(default)]
+    pub links: BTreeMap<UserId, UserId>,
+    /// The longest run of survivors in a row, for `?!record`
+    #[serde(default)]
+    pub survivor_record: Option<SurvivorRecord>,
+    /// The channel profile picked by mods with `?!profile`, none to go by `--assign-profile`
+    #[serde(default)]
+    pub profile: Option<crate::profiles::Choice>,
+    /// Sum of the stats of every user, cleared whenever the channel's stats change
+    #[serde(skip)]
+    totals: Option<Totals>,
+}
+
+/// The most spins in a row that everyone survived in a channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurvivorRecord {
+    pub count: u64,
+    /// Name of the survivor who took the run to `count`
+    pub holder: String,
+    pub set_at: DateTime<Utc>,
+}
+
+/// Stats of a whole channel, for `?!stats global`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Totals {
+    pub survivals: u64,
+    pub deaths: u64,
+}
+
+impl Totals {
+    pub fn spins(&self) -> u64 {
+        self.survivals + self.deaths
+    }
+}
+
+/// Whether roulette may be played in a channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Safety {
+    /// Open, except during the quiet hours
+    #[default]
+    Auto,
+    /// Closed, even outside the quiet hours
+    On,
+    /// Open, even during the quiet hours
+    Off,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserStats {
+    /// Last known login of the user
+    pub login: UserName,
+    pub survivals: u64,
+    pub deaths: u64,
+    /// Spins where the bullet only grazed the user, with `--graze-chance`
+    #[serde(default)]
+    pub grazes: u64,
+    /// Survivals since the last death
+    pub streak: u64,
+    /// Deaths since the last survival, for `--pity-after`
+    #[serde(default)]
+    pub losing_streak: u64,
+    pub longest_streak: u64,
+    /// Coins flipped with `?!flip`
+    #[serde(default)]
+    pub flips: u64,
+    /// Flips where the user called the right side
+    #[serde(default)]
+    pub flips_won: u64,
+    /// Times the user timed themselves out with `?!giveup`
+    #[serde(default)]
+    pub giveups: u64,
+    /// When the user last spun, unknown for spins from before this was recorded
+    #[serde(default)]
+    pub last_played: Option<DateTime<Utc>>,
+    /// Earned with `--points-per-survival`, spent on `?!insure`
+    #[serde(default)]
+    pub points: u64,
+    /// Whether the user bought insurance for their next spin
+    #[serde(default)]
+    pub insured: bool,
+    /// Shields bought with `?!shield` or granted by mods, each one skips a cooldown
+    #[serde(default)]
+    pub shields: u64,
+    /// The day `spins_today` counts the spins of, for `--daily-limit`
+    #[serde(default)]
+    pub day: Option<NaiveDate>,
+    #[serde(default)]
+    pub spins_today: u64,
+    /// Language picked with `?!lang`, `None` for `--default-language`
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+impl UserStats {
+    /// Every spin of the user, however it ended.
+    pub fn spins(&self) -> u64 {
+        self.survivals + self.deaths + self.grazes
+    }
+
+    fn new(login: UserName) -> Self {
+        Self {
+            login,
+            survivals: 0,
+            deaths: 0,
+            grazes: 0,
+            streak: 0,
+            losing_streak: 0,
+            longest_streak: 0,
+            flips: 0,
+            flips_won: 0,
+            giveups: 0,
+            last_played: None,
+            points: 0,
+            insured: false,
+            shields: 0,
+            day: None,
+            spins_today: 0,
+            language: None,
+        }
+    }
+}
+
+/// Why `?!insure` didn't sell insurance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsuranceError {
+    AlreadyInsured,
+    /// The user can't afford it, with the points they have
+    NotEnoughPoints(u64),
+}
+
+/// Why the user didn't get a shield with `?!shield`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShieldError {
+    /// The user holds `--max-shields` already
+    Full,
+    /// The user can't afford it, with the points they have
+    NotEnoughPoints(u64),
+}
+
+/// Spins in a channel since its stream went online, only kept in memory for `--session-report`.
+#[derive(Debug, Default)]
+pub struct Session {
+    pub spins: u64,
+    pub survivals: u64,
+    /// Display name and number of deaths of everyone who got shot
+    pub deaths: HashMap<UserId, (String, u64)>,
+}
+
+impl Session {
+    pub fn record(&mut self, user: &UserId, name: &str, survived: bool) {
+        self.spins += 1;
+        if survived {
+            self.survivals += 1;
+        } else {
+            let (last_name, deaths) = self
+                .deaths
+                .entry(user.clone())
+                .or_insert_with(|| (name.to_owned(), 0));
+            name.clone_into(last_name);
+            *deaths += 1;
+        }
+    }
+
+    /// The player who got shot the most, and how often.
+    pub fn unluckiest(&self) -> Option<(&str, u64)> {
+        self.deaths
+            .values()
+            .max_by_key(|(_, deaths)| *deaths)
+            .map(|(name, deaths)| (name.as_str(), *deaths))
+    }
+}
+
+/// Bring a stats file of version `from` up to the next version.
+///
+/// Fields that can simply start out empty get a `#[serde(default)]` instead, a migration is only
+/// needed when existing data has to change.
+fn migrate(_value: &mut serde_json::Value, from: u64) {
+    match from {
+        // Version 1 had no version number, everything it lacks has a default.
+        1 => {}
+        _ => unreachable!("no migration from stats version {from}"),
+    }
+}
+
+impl Stats {
+    /// Load the stats from `path`, starting out empty if the file doesn't exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, eyre::Report> {
+        let path = path.into();
+        let mut stats: Stats = match std::fs::read_to_string(&path) {
+            Ok(s) => {
+                let mut value: serde_json::Value = serde_json::from_str(&s)
+                    .wrap_err_with(|| format!("couldn't parse stats file {}", path.display()))?;
+                let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+                if version > VERSION {
+                    eyre::bail!(
+                        "stats file {} is version {version}, which is newer than this bot understands ({VERSION})",
+                        path.display()
+                    );
+                }
+                if version < 1 {
+                    eyre::bail!(
+                        "stats file {} is version {version}, but there's no version before 1",
+                        path.display()
+                    );
+                }
+                if version < VERSION {
+                    // Keep the old file around in case the migration goes wrong.
+                    let backup = path.with_extension(format!("json.v{version}"));
+                    std::fs::copy(&path, &backup).wrap_err_with(|| {
+                        format!("couldn't back up stats file to {}", backup.display())
+                    })?;
+                    tracing::info!(
+                        "migrating stats file {} from version {version} to {VERSION}, the old one is kept as {}",
+                        path.display(),
+                        backup.display()
+                    );
+                    for from in version..VERSION {
+                        migrate(&mut value, from);
+                    }
+                }
+                serde_json::from_value(value)
+                    .wrap_err_with(|| format!("couldn't parse stats file {}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Stats::default(),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("couldn't read stats file {}", path.display()))
+            }
+        };
+        stats.version = VERSION;
+        stats.path = path;
+        Ok(stats)
+    }
+
+    /// Write changes at most every `interval` instead of one by one, or once `changes` of them are waiting.
+    ///
+    /// Whatever hasn't been written is lost if the bot crashes, so [`flush`](Self::flush) has to be called
+    /// every `interval` and before stopping.
+    pub fn batch_writes(&mut self, interval: std::time::Duration, changes: u64) {
+        self.batching = Batching {
+            interval,
+            changes,
+            pending: 0,
+            written: Some(std::time::Instant::now()),
+        };
+    }
+
+    /// Write the stats back to the file they were loaded from, or only once it's due with
+    /// [batching](Self::batch_writes).
+    pub fn save(&mut self) -> Result<(), eyre::Report> {
+        let batching = &mut self.batching;
+        batching.pending += 1;
+        let due = batching.interval.is_zero()
+            || batching.pending >= batching.changes
+            || batching
+                .written
+                .is_none_or(|written| written.elapsed() >= batching.interval);
+        if due {
+            self.write()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the changes that are waiting, if any.
+    pub fn flush(&mut self) -> Result<(), eyre::Report> {
+        if self.batching.pending == 0 {
+            return Ok(());
+        }
+        self.write()
+    }
+
+    fn write(&mut self) -> Result<(), eyre::Report> {
+        // Write to a temporary file first so a crash can't leave the stats half written.
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("couldn't write stats file {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .wrap_err_with(|| format!("couldn't write stats file {}", self.path.display()))?;
+        self.batching.pending = 0;
+        self.batching.written = Some(std::time::Instant::now());
+        Ok(())
+    }
+
+    /// Record the result of a spin.
+    pub fn record(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        survived: bool,
+    ) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.last_played = Some(Utc::now());
+        if survived {
+            stats.survivals += 1;
+            stats.streak += 1;
+            stats.longest_streak = stats.longest_streak.max(stats.streak);
+            stats.losing_streak = 0;
+        } else {
+            stats.deaths += 1;
+            stats.streak = 0;
+            stats.losing_streak += 1;
+        }
+    }
+
+    /// Record a spin where the bullet only grazed the user, which leaves their streak alone.
+    pub fn record_graze(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+    ) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.last_played = Some(Utc::now());
+        stats.grazes += 1;
+    }
+
+    /// Record a coin flip, `won` is whether the user called it right if they made a call.
+    pub fn record_flip(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        won: Option<bool>,
+    ) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.flips += 1;
+        if won == Some(true) {
+            stats.flips_won += 1;
+        }
+    }
+
+    /// Record someone timing themselves out with `?!giveup`.
+    pub fn record_giveup(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+    ) {
+        self.user(broadcaster, broadcaster_login, user, user_login)
+            .giveups += 1;
+    }
+
+    pub fn add_points(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        points: u64,
+    ) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.points = stats.points.saturating_add(points);
+    }
+
+    /// Set the user's points to `points`, for `?!setpoints`.
+    pub fn set_points(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        points: u64,
+    ) {
+        self.user(broadcaster, broadcaster_login, user, user_login)
+            .points = points;
+    }
+
+    /// Add `points` to the user's points, or take them away if negative, returning the new balance.
+    ///
+    /// Taking away more than they have fails with the points they have, unless `clamp` is set, which leaves them
+    /// with none.
+    pub fn adjust_points(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        points: i64,
+        clamp: bool,
+    ) -> Result<u64, u64> {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.points = match stats.points.checked_add_signed(points) {
+            Some(balance) => balance,
+            None if points > 0 => u64::MAX,
+            None if clamp => 0,
+            None => return Err(stats.points),
+        };
+        Ok(stats.points)
+    }
+
+    /// Double the user's streak and points after they won `?!double`, returning both.
+    pub fn double_up(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+    ) -> (u64, u64) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        stats.last_played = Some(Utc::now());
+        // Points set by mods can be as high as they go already.
+        stats.streak = stats.streak.saturating_mul(2);
+        stats.longest_streak = stats.longest_streak.max(stats.streak);
+        stats.points = stats.points.saturating_mul(2);
+        (stats.streak, stats.points)
+    }
+
+    /// Set the language the user gets outcome messages in, `None` for `--default-language`.
+    pub fn set_language(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        language: Option<String>,
+    ) {
+        self.user(broadcaster, broadcaster_login, user, user_login)
+            .language = language;
+    }
+
+    /// Spend `cost` points on insurance for the user's next spin, returning the points they have left.
+    pub fn buy_insurance(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        cost: u64,
+    ) -> Result<u64, InsuranceError> {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        if stats.insured {
+            return Err(InsuranceError::AlreadyInsured);
+        }
+        stats.points = stats
+            .points
+            .checked_sub(cost)
+            .ok_or(InsuranceError::NotEnoughPoints(stats.points))?;
+        stats.insured = true;
+        Ok(stats.points)
+    }
+
+    /// Use up the user's insurance, returning whether they had any.
+    pub fn take_insurance(&mut self, broadcaster: &UserId, user: &UserId) -> bool {
+        self.channels
+            .get_mut(broadcaster)
+            .and_then(|c| c.users.get_mut(user))
+            .is_some_and(|u| std::mem::take(&mut u.insured))
+    }
+
+    /// Give the user a shield, for `cost` points unless it's zero, returning how many shields and points they have.
+    ///
+    /// Nobody holds more than `max` shields.
+    pub fn add_shield(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        cost: u64,
+        max: u64,
+    ) -> Result<(u64, u64), ShieldError> {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        if stats.shields >= max {
+            return Err(ShieldError::Full);
+        }
+        stats.points = stats
+            .points
+            .checked_sub(cost)
+            .ok_or(ShieldError::NotEnoughPoints(stats.points))?;
+        stats.shields += 1;
+        Ok((stats.shields, stats.points))
+    }
+
+    /// Use up one of the user's shields, returning how many are left if they had one.
+    pub fn take_shield(&mut self, broadcaster: &UserId, user: &UserId) -> Option<u64> {
+        let stats = self
+            .channels
+            .get_mut(broadcaster)
+            .and_then(|c| c.users.get_mut(user))
+            .filter(|u| u.shields > 0)?;
+        stats.shields -= 1;
+        Some(stats.shields)
+    }
+
+    /// Count a spin towards the user's `--daily-limit` on `day`.
+    pub fn record_daily_spin(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+        day: NaiveDate,
+    ) {
+        let stats = self.user(broadcaster, broadcaster_login, user, user_login);
+        if stats.day != Some(day) {
+            stats.day = Some(day);
+            stats.spins_today = 0;
+        }
+        stats.spins_today += 1;
+    }
+
+    /// How often the user spun in the channel on `day`, if it's the day they last spun.
+    pub fn spins_on(&self, broadcaster: &UserId, user: &UserId, day: NaiveDate) -> u64 {
+        self.user_stats(broadcaster, user)
+            .filter(|u| u.day == Some(day))
+            .map_or(0, |u| u.spins_today)
+    }
+
+    /// Whether the user has spun in the channel before.
+    pub fn has_played(&self, broadcaster: &UserId, user: &UserId) -> bool {
+        self.channels
+            .get(broadcaster)
+            .and_then(|c| c.users.get(user))
+            .is_some_and(|u| u.spins() > 0)
+    }
+
+    /// When the user last spun in the channel, if known.
+    pub fn last_played(&self, broadcaster: &UserId, user: &UserId) -> Option<DateTime<Utc>> {
+        self.channels
+            .get(broadcaster)
+            .and_then(|c| c.users.get(user))
+            .and_then(|u| u.last_played)
+    }
+
+    /// The safety setting of a channel.
+    pub fn safety(&self, broadcaster: &UserId) -> Safety {
+        self.channels
+            .get(broadcaster)
+            .map(|c| c.safety)
+            .unwrap_or_default()
+    }
+
+    pub fn set_safety(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        safety: Safety,
+    ) {
+        self.channel(broadcaster, broadcaster_login).safety = safety;
+    }
+
+    /// The channel profile a mod picked for the channel with `?!profile`.
+    pub fn profile_choice(&self, broadcaster: &UserId) -> Option<&crate::profiles::Choice> {
+        self.channels.get(broadcaster)?.profile.as_ref()
+    }
+
+    pub fn set_profile_choice(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        choice: Option<crate::profiles::Choice>,
+    ) {
+        self.channel(broadcaster, broadcaster_login).profile = choice;
+    }
+
+    /// Whether a mod turned the command with `key` on or off in the channel.
+    pub fn command_enabled(&self, broadcaster: &UserId, key: &str) -> Option<bool> {
+        self.channels
+            .get(broadcaster)
+            .and_then(|c| c.commands.get(key).copied())
+    }
+
+    pub fn set_command_enabled(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        key: &str,
+        enabled: bool,
+    ) {
+        self.channel(broadcaster, broadcaster_login)
+            .commands
+            .insert(key.to_owned(), enabled);
+    }
+
+    pub fn survivor_record(&self, broadcaster: &UserId) -> Option<&SurvivorRecord> {
+        self.channels.get(broadcaster)?.survivor_record.as_ref()
+    }
+
+    /// Make a run of `count` survivors the channel's record if it's longer, `holder` being the last of them.
+    ///
+    /// Returns the record it beat, 0 if there was none, or `None` if it's no record.
+    pub fn beat_survivor_record(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        count: u64,
+        holder: &str,
+    ) -> Option<u64> {
+        let channel = self.channel(broadcaster, broadcaster_login);
+        let previous = channel.survivor_record.as_ref().map_or(0, |r| r.count);
+        if count <= previous {
+            return None;
+        }
+        channel.survivor_record = Some(SurvivorRecord {
+            count,
+            holder: holder.to_owned(),
+            set_at: Utc::now(),
+        });
+        Some(previous)
+    }
+
+    /// The account `user` shares a cooldown with, which is `user` unless a mod linked it as an alt.
+    pub fn linked(&self, broadcaster: &UserId, user: &UserId) -> UserId {
+        self.channels
+            .get(broadcaster)
+            .and_then(|c| c.links.get(user))
+            .unwrap_or(user)
+            .clone()
+    }
+
+    /// Link `alt` to `main` so they share a cooldown, along with any alts of `alt`.
+    ///
+    /// An alt of an alt is linked to the account at the end of the chain, so the links are never more than one
+    /// step. Returns false if `main` is linked to `alt` already, the other way around.
+    pub fn link(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        alt: &UserId,
+        main: &UserId,
+    ) -> bool {
+        let main = self.linked(broadcaster, main);
+        if main == *alt {
+            return false;
+        }
+        let links = &mut self.channel(broadcaster, broadcaster_login).links;
+        for linked in links.values_mut() {
+            if linked == alt {
+                *linked = main.clone();
+            }
+        }
+        links.insert(alt.clone(), main);
+        true
+    }
+
+    /// Undo `?!link` for `alt`, returning whether it was linked.
+    pub fn unlink(&mut self, broadcaster: &UserId, alt: &UserId) -> bool {
+        self.channels
+            .get_mut(broadcaster)
+            .is_some_and(|c| c.links.remove(alt).is_some())
+    }
+
+    /// Whether a mod put the login on the ignore list of the channel.
+    pub fn is_ignored(&self, broadcaster: &UserId, login: &str) -> bool {
+        self.channels
+            .get(broadcaster)
+            .is_some_and(|c| c.ignored.contains(login))
+    }
+
+    pub fn set_ignored(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        login: &str,
+        ignored: bool,
+    ) {
+        let channel = self.channel(broadcaster, broadcaster_login);
+        if ignored {
+            channel.ignored.insert(login.to_owned());
+        } else {
+            channel.ignored.remove(login);
+        }
+    }
+
+    pub fn is_protected(&self, broadcaster: &UserId, user: &UserId) -> bool {
+        self.channels
+            .get(broadcaster)
+            .is_some_and(|c| c.protected.contains(user))
+    }
+
+    pub fn set_protected(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        protected: bool,
+    ) {
+        let channel = self.channel(broadcaster, broadcaster_login);
+        if protected {
+            channel.protected.insert(user.clone());
+        } else {
+            channel.protected.remove(user);
+        }
+    }
+
+    /// Whether the bot made the user VIP, with `--vip-streak`.
+    pub fn is_granted_vip(&self, broadcaster: &UserId, user: &UserId) -> bool {
+        self.channels
+            .get(broadcaster)
+            .is_some_and(|c| c.vips.contains(user))
+    }
+
+    pub fn set_granted_vip(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        vip: bool,
+    ) {
+        let channel = self.channel(broadcaster, broadcaster_login);
+        if vip {
+            channel.vips.insert(user.clone());
+        } else {
+            channel.vips.remove(user);
+        }
+    }
+
+    /// The `n` players with the most survivals, adding up their stats in every channel.
+    ///
+    /// Returns the login, survivals and deaths of each, fewer deaths breaking ties.
+    pub fn top(&self, n: usize) -> Vec<(&UserName, u64, u64)> {
+        let mut players: HashMap<&UserId, (&UserName, u64, u64)> = HashMap::new();
+        for channel in self.channels.values() {
+            for (id, user) in &channel.users {
+                let player = players.entry(id).or_insert((&user.login, 0, 0));
+                player.1 += user.survivals;
+                player.2 += user.deaths;
+            }
+        }
+        let mut players: Vec<_> = players
+            .into_values()
+            .filter(|(_, survivals, _)| *survivals > 0)
+            .collect();
+        players.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)).then(a.0.cmp(b.0)));
+        players.truncate(n);
+        players
+    }
+
+    /// Every player who spun, best first like `top` ranks them or with the most deaths first, adding up their
+    /// stats in every channel.
+    pub fn ranking(&self, by: RankBy) -> Vec<UserId> {
+        let mut players: HashMap<&UserId, (&UserName, u64, u64)> = HashMap::new();
+        for channel in self.channels.values() {
+            for (id, user) in &channel.users {
+                let player = players.entry(id).or_insert((&user.login, 0, 0));
+                player.1 += user.survivals;
+                player.2 += user.deaths;
+            }
+        }
+        let mut players: Vec<_> = players
+            .into_iter()
+            .filter(|(_, (_, survivals, deaths))| survivals + deaths > 0)
+            .collect();
+        players.sort_by(|(_, a), (_, b)| {
+            match by {
+                RankBy::Survivals => b.1.cmp(&a.1).then(a.2.cmp(&b.2)),
+                RankBy::Deaths => b.2.cmp(&a.2).then(a.1.cmp(&b.1)),
+            }
+            .then(a.0.cmp(b.0))
+        });
+        players.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// The stats of a user in the channel, if they played there.
+    pub fn user_stats(&self, broadcaster: &UserId, user: &UserId) -> Option<&UserStats> {
+        self.channels.get(broadcaster)?.users.get(user)
+    }
+
+    /// The stats of every user in the channel added up.
+    pub fn totals(&mut self, broadcaster: &UserId) -> Totals {
+        let Some(channel) = self.channels.get_mut(broadcaster) else {
+            return Totals::default();
+        };
+        *channel.totals.get_or_insert_with(|| {
+            channel
+                .users
+                .values()
+                .fold(Totals::default(), |totals, user| Totals {
+                    survivals: totals.survivals + user.survivals,
+                    deaths: totals.deaths + user.deaths,
+                })
+        })
+    }
+
+    /// Find the id of a user who played in the channel by their last known login.
+    pub fn find_user(&self, broadcaster: &UserId, login: &str) -> Option<UserId> {
+        self.channels
+            .get(broadcaster)?
+            .users
+            .iter()
+            .find(|(_, u)| u.login.as_str() == login)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Add the stats of `from` to those of `to` and remove `from`, e.g. after someone moved to a new account.
+    ///
+    /// `to` keeps its current streak. Returns false if `from` has no stats in the channel.
+    pub fn merge_users(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        from: &UserId,
+        to: &UserId,
+        to_login: &UserName,
+    ) -> bool {
+        let Some(from) = self
+            .channels
+            .get_mut(broadcaster)
+            .and_then(|c| c.users.remove(from))
+        else {
+            return false;
+        };
+        let to = self.user(broadcaster, broadcaster_login, to, to_login);
+        if to.spins() == 0 {
+            to.streak = from.streak;
+            to.losing_streak = from.losing_streak;
+        }
+        to.survivals += from.survivals;
+        to.deaths += from.deaths;
+        to.grazes += from.grazes;
+        to.longest_streak = to.longest_streak.max(from.longest_streak);
+        to.flips += from.flips;
+        to.flips_won += from.flips_won;
+        to.giveups += from.giveups;
+        to.last_played = to.last_played.max(from.last_played);
+        to.points = to.points.saturating_add(from.points);
+        to.insured |= from.insured;
+        to.shields += from.shields;
+        to.language = to.language.take().or(from.language);
+        if from.day > to.day {
+            to.day = from.day;
+            to.spins_today = from.spins_today;
+        } else if from.day == to.day {
+            to.spins_today += from.spins_today;
+        }
+        true
+    }
+
+    /// Get the stats of a channel, creating them if nobody played in it before.
+    fn channel(&mut self, broadcaster: &UserId, broadcaster_login: &UserName) -> &mut ChannelStats {
+        let channel = self
+            .channels
+            .entry(broadcaster.clone())
+            .or_insert_with(|| ChannelStats {
+                login: broadcaster_login.clone(),
+                users: BTreeMap::new(),
+                safety: Safety::default(),
+                commands: BTreeMap::new(),
+                ignored: BTreeSet::new(),
+                protected: BTreeSet::new(),
+                vips: BTreeSet::new(),
+                links: BTreeMap::new(),
+                survivor_record: None,
+                profile: None,
+                totals: None,
+            });
+        channel.login = broadcaster_login.clone();
+        // Anything that gets the channel mutably might change it.
+        channel.totals = None;
+        channel
+    }
+
+    /// Get the stats of a user, creating them if they haven't played in the channel before.
+    fn user(
+        &mut self,
+        broadcaster: &UserId,
+        broadcaster_login: &UserName,
+        user: &UserId,
+        user_login: &UserName,
+    ) -> &mut UserStats {
+        let stats = self
+            .channel(broadcaster, broadcaster_login)
+            .users
+            .entry(user.clone())
+            .or_insert_with(|| UserStats::new(user_login.clone()));
+        stats.login = user_login.clone();
+        stats
+    }
+
+    /// Export the stats as csv, only including the channel with login `channel` if given.
+    pub fn export_csv(&self, path: &Path, channel: Option<&UserName>) -> Result<(), eyre::Report> {
+        let channels: Vec<_> = self
+            .channels
+            .values()
+            .filter(|c| channel.is_none_or(|login| c.login == *login))
+            .collect();
+        if let Some(login) = channel {
+            if channels.is_empty() {
+                eyre::bail!("No stats found for channel: {login}");
+            }
+        }
+
+        let mut file = std::io::BufWriter::new(
+            std::fs::File::create(path)
+                .wrap_err_with(|| format!("couldn't create {}", path.display()))?,
+        );
+        // Logins are limited to letters, digits and underscores, so nothing needs quoting.
+        writeln!(file, "channel,user,survivals,deaths,longest_streak,grazes")?;
+        for c in channels {
+            for user in c.users.values() {
+                writeln!(
+                    file,
+                    "{},{},{},{},{},{}",
+                    c.login,
+                    user.login,
+                    user.survivals,
+                    user.deaths,
+                    user.longest_streak,
+                    user.grazes
+                )?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stats file from before there was a version number.
+    const V1: &str = r#"{
+        "channels": {
+            "1": {
+                "login": "alice",
+                "users": {
+                    "2": {
+                        "login": "bob",
+                        "survivals": 7,
+                        "deaths": 3,
+                        "streak": 2,
+                        "longest_streak": 5
+                    }
+                }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn points_stop_at_the_most_there_can_be() {
+        let mut stats = Stats::default();
+        let (channel, login) = (UserId::from("1"), UserName::from("alice"));
+        let (user, user_login) = (UserId::from("2"), UserName::from("bob"));
+        stats.set_points(&channel, &login, &user, &user_login, u64::MAX - 1);
+        assert_eq!(
+            stats.double_up(&channel, &login, &user, &user_login).1,
+            u64::MAX
+        );
+        stats.add_points(&channel, &login, &user, &user_login, 10);
+        assert_eq!(
+            stats.user_stats(&channel, &user).map(|s| s.points),
+            Some(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn version_1_files_are_migrated() {
+        let dir = std::env::temp_dir().join(format!("roulette-stats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stats.json");
+        std::fs::write(&path, V1).unwrap();
+
+        let stats = Stats::load(&path).unwrap();
+        assert_eq!(stats.version, VERSION);
+        let channel = &stats.channels[&UserId::from("1")];
+        assert_eq!(channel.login.as_str(), "alice");
+        assert_eq!(channel.safety, Safety::Auto);
+        assert!(channel.protected.is_empty());
+        let user = &channel.users[&UserId::from("2")];
+        assert_eq!(user.login.as_str(), "bob");
+        assert_eq!(
+            (
+                user.survivals,
+                user.deaths,
+                user.streak,
+                user.longest_streak
+            ),
+            (7, 3, 2, 5)
+        );
+        assert_eq!((user.grazes, user.points, user.shields), (0, 0, 0));
+        assert_eq!(user.last_played, None);
+        // The old file is kept in case the migration went wrong.
+        assert_eq!(
+            std::fs::read_to_string(dir.join("stats.json.v1")).unwrap(),
+            V1
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn version_0_files_are_refused() {
+        let dir = std::env::temp_dir().join(format!("roulette-stats-v0-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stats.json");
+        std::fs::write(&path, r#"{"version": 0, "channels": {}}"#).unwrap();
+        assert!(Stats::load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
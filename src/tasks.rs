@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+/// What happens to a background task once `--max-tasks` are running.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskOverflow {
+    /// Start it once a slot frees up, tasks that waited longest go first
+    Wait,
+    /// Skip it and log a warning
+    Drop,
+}
+
+/// The side effects the bot runs in the background, like webhooks, outcome commands and `--unban-after`, capped
+/// at `--max-tasks` so a spike in chat can't pile them up without end.
+#[derive(Debug, Clone)]
+pub struct Tasks {
+    permits: Arc<Semaphore>,
+    /// Tasks running or waiting for a slot, shared with the metrics
+    pending: Arc<AtomicU64>,
+    overflow: TaskOverflow,
+}
+
+impl Tasks {
+    pub fn new(max: usize, overflow: TaskOverflow, pending: Arc<AtomicU64>) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max)),
+            pending,
+            overflow,
+        }
+    }
+
+    /// Run `task` in the background, `what` naming it in the logs if it has to wait or is dropped.
+    pub fn spawn(
+        &self,
+        what: &'static str,
+        task: impl std::future::Future<Output = ()> + Send + 'static,
+    ) {
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) if self.overflow == TaskOverflow::Drop => {
+                tracing::warn!("too many tasks running, dropping {what}");
+                return;
+            }
+            Err(_) => {
+                tracing::debug!("too many tasks running, {what} waits for a slot");
+                None
+            }
+        };
+        let permits = self.permits.clone();
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let pending = Pending(self.pending.clone());
+        tokio::spawn(async move {
+            let _pending = pending;
+            let _permit = match permit {
+                Some(permit) => permit,
+                None => permits
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            };
+            task.await;
+        });
+    }
+}
+
+/// Counts a task as done once it's dropped, so one that panics doesn't stay pending and hold up the shutdown.
+#[derive(Debug)]
+struct Pending(Arc<AtomicU64>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_panicking_task_isnt_pending_anymore() {
+        let pending = Arc::new(AtomicU64::new(0));
+        let tasks = Tasks::new(4, TaskOverflow::Wait, pending.clone());
+        tasks.spawn("a panic", async { panic!("the task broke") });
+        tasks.spawn("a task", async {});
+        for _ in 0..100 {
+            if pending.load(Ordering::Relaxed) == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!(
+            "{} tasks are still pending",
+            pending.load(Ordering::Relaxed)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+/// Wait before the first retry of a webhook event, doubled for every retry after it.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Posts json events to a url without waiting for the response.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    client: reqwest::Client,
+    url: url::Url,
+    tasks: crate::tasks::Tasks,
+    /// Tries after the first one before an event is given up on
+    retries: u32,
+    /// Where events go that couldn't be delivered, if anywhere
+    dead_letters: Option<Arc<DeadLetters>>,
+}
+
+impl Webhook {
+    pub fn new(
+        client: reqwest::Client,
+        url: url::Url,
+        tasks: crate::tasks::Tasks,
+        retries: u32,
+        dead_letters: Option<Arc<DeadLetters>>,
+    ) -> Self {
+        Self {
+            client,
+            url,
+            tasks,
+            retries,
+            dead_letters,
+        }
+    }
+
+    /// Send `event` in the background, retrying with backoff and then giving it to the dead letters.
+    pub fn post(&self, event: &impl Serialize) {
+        if let Some(body) = body(event) {
+            self.post_body(body);
+        }
+    }
+
+    fn post_body(&self, body: Vec<u8>) {
+        let webhook = self.clone();
+        self.tasks.spawn(
+            "a webhook event",
+            async move { webhook.deliver(body).await },
+        );
+    }
+
+    /// Send `event` and wait until it's delivered, for events sent right before the bot stops.
+    pub async fn post_now(&self, event: &impl Serialize) {
+        if let Some(body) = body(event) {
+            self.deliver(body).await;
+        }
+    }
+
+    async fn deliver(&self, body: Vec<u8>) {
+        let url = &self.url;
+        let mut backoff = BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .client
+                .post(url.clone())
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            let e = match result {
+                Ok(_) => {
+                    tracing::debug!(%url, "delivered webhook event");
+                    return;
+                }
+                Err(e) => e,
+            };
+            // The receiver turning the event down won't change, unless it's too busy for it.
+            let rejected = e.status().is_some_and(|status| {
+                status.is_client_error() && status != http::StatusCode::TOO_MANY_REQUESTS
+            });
+            if rejected || attempt >= self.retries {
+                tracing::warn!(%url, "couldn't deliver webhook event: {e}");
+                if let Some(dead_letters) = &self.dead_letters {
+                    dead_letters.add(url, &body);
+                }
+                return;
+            }
+            tracing::debug!(
+                %url,
+                "couldn't deliver webhook event, trying again in {}ms: {e}",
+                backoff.as_millis()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+fn body(event: &impl Serialize) -> Option<Vec<u8>> {
+    match serde_json::to_vec(event) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            tracing::error!("couldn't serialize webhook event: {e}");
+            None
+        }
+    }
+}
+
+/// The `--webhook-dead-letters` file, where webhook events are appended as json lines once every try at them
+/// failed, for `--replay-dead-letters` to send again.
+#[derive(Debug)]
+pub struct DeadLetters {
+    path: PathBuf,
+    /// Held while the file is written, so the lines of two events can't mix
+    lock: std::sync::Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeadLetter {
+    url: String,
+    failed_at: chrono::DateTime<chrono::Utc>,
+    event: serde_json::Value,
+}
+
+impl DeadLetters {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: std::sync::Mutex::new(()),
+        }
+    }
+
+    fn add(&self, url: &url::Url, body: &[u8]) {
+        let line = serde_json::from_slice(body).and_then(|event| {
+            serde_json::to_string(&DeadLetter {
+                url: url.to_string(),
+                failed_at: chrono::Utc::now(),
+                event,
+            })
+        });
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let written = line.map_err(std::io::Error::from).and_then(|line| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|mut file| writeln!(file, "{line}"))
+        });
+        match written {
+            Ok(()) => tracing::info!(%url, "kept the webhook event in {}", self.path.display()),
+            Err(e) => tracing::error!(
+                %url,
+                "couldn't keep the webhook event in {}: {e}",
+                self.path.display()
+            ),
+        }
+    }
+
+    /// Send the events in the file again in the background, emptying it. The ones that fail again go back in.
+    ///
+    /// Events go to the url they were meant for, even if that webhook isn't set anymore.
+    pub fn replay(
+        self: &Arc<Self>,
+        client: reqwest::Client,
+        tasks: &crate::tasks::Tasks,
+        retries: u32,
+    ) -> Result<(), eyre::Report> {
+        let text = {
+            let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+            let text = match std::fs::read_to_string(&self.path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| {
+                        format!("couldn't read dead letters {}", self.path.display())
+                    })
+                }
+            };
+            std::fs::write(&self.path, "")
+                .wrap_err_with(|| format!("couldn't empty dead letters {}", self.path.display()))?;
+            text
+        };
+        let mut replayed = 0;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let letter = serde_json::from_str::<DeadLetter>(line)
+                .map_err(eyre::Report::from)
+                .and_then(|letter| Ok((letter.url.parse::<url::Url>()?, letter.event)));
+            let (url, event) = match letter {
+                Ok(letter) => letter,
+                Err(e) => {
+                    tracing::warn!("skipping a dead letter that couldn't be read: {e}");
+                    continue;
+                }
+            };
+            let Some(body) = body(&event) else {
+                continue;
+            };
+            Webhook::new(
+                client.clone(),
+                url,
+                tasks.clone(),
+                retries,
+                Some(self.clone()),
+            )
+            .post_body(body);
+            replayed += 1;
+        }
+        tracing::info!(
+            "sending {replayed} webhook event(s) from {} again",
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Posts to `--heartbeat-webhook` at most once every `--heartbeat-interval`, so a monitor can tell the bot died
+/// when the heartbeats stop.
+///
+/// It beats from the loop reading twitch, for every event or keepalive, so a bot that's running but stuck stops
+/// beating too.
+#[derive(Debug)]
+pub struct Heartbeat {
+    webhook: Webhook,
+    interval: Duration,
+    last: std::sync::Mutex<Option<Instant>>,
+    beats: AtomicU64,
+}
+
+impl Heartbeat {
+    pub fn new(webhook: Webhook, interval: Duration) -> Self {
+        Self {
+            webhook,
+            interval,
+            last: std::sync::Mutex::new(None),
+            beats: AtomicU64::new(0),
+        }
+    }
+
+    /// Post a heartbeat, unless the last one was less than an interval ago.
+    pub fn beat(&self) {
+        {
+            let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+            if last.is_some_and(|last| last.elapsed() < self.interval) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        self.webhook.post(&HeartbeatEvent {
+            kind: "heartbeat",
+            beat: self.beats.fetch_add(1, Ordering::Relaxed) + 1,
+        });
+    }
+}
+
+/// Event sent to `--overlay-webhook` whenever someone gets shot.
+#[derive(Debug, Serialize)]
+pub struct ShotEvent<'a> {
+    /// Always `"shot"`, so overlays can tell events apart if more are added
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Login of the channel the spin happened in
+    pub channel: &'a str,
+    /// Display name of the player
+    pub user: &'a str,
+    /// Length of the timeout in seconds
+    pub duration: u32,
+    /// Whether the player couldn't be timed out, e.g. because they're a moderator
+    pub immune: bool,
+}
+
+/// Event sent to `--monitoring-webhook` when something goes wrong with the connection to twitch.
+#[derive(Debug, Serialize)]
+pub struct MonitorEvent<'a> {
+    /// `"reconnect"` or `"token_refresh_failed"`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// What happened, in words
+    pub reason: &'a str,
+    /// How many times this happened since the bot started, including this one
+    pub attempt: u64,
+}
+
+/// Event sent to `--heartbeat-webhook` while the bot is healthy.
+#[derive(Debug, Serialize)]
+pub struct HeartbeatEvent {
+    /// Always `"heartbeat"`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// How many heartbeats were sent since the bot started, including this one
+    pub beat: u64,
+}
@@ -1,10 +1,13 @@
 use eyre::WrapErr;
 use futures::TryStreamExt;
+use std::collections::{HashSet, VecDeque};
+use std::ops::ControlFlow;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio_tungstenite::tungstenite;
 use tracing::Instrument;
 
+use twitch_api::twitch_oauth2::{TwitchToken, UserToken};
 use twitch_api::{
     eventsub::{
         self,
@@ -14,7 +17,16 @@ use twitch_api::{
     types::{self},
     HelixClient,
 };
-use twitch_api::twitch_oauth2::{TwitchToken, UserToken};
+
+/// How the bot finds out which subscriptions a session already has when it's welcomed, to only create the
+/// missing ones.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resubscribe {
+    /// Ask twitch for the session's subscriptions
+    Query,
+    /// Remember the subscriptions the bot created, which saves a request but misses changes made elsewhere
+    Track,
+}
 
 pub struct ChatWebsocketClient {
     /// The session id of the websocket connection
@@ -27,6 +39,135 @@ pub struct ChatWebsocketClient {
     pub connect_url: url::Url,
     /// Chats to connect to.
     pub chats: Vec<twitch_api::types::UserId>,
+    /// Message to post in each chat once subscribed, keyed by broadcaster, empty for no welcome
+    pub welcome_messages: std::collections::HashMap<twitch_api::types::UserId, String>,
+    /// Post the welcome message after reconnects too, not only the first connect
+    pub welcome_on_reconnect: bool,
+    /// Whether the welcome message was posted already
+    pub welcomed: bool,
+    /// Also subscribe to the chats going online and offline
+    pub stream_events: bool,
+    /// Also subscribe to twitch clearing someone's messages, which it does when they're banned or timed out
+    pub clears: bool,
+    /// Also subscribe to channel points redemptions, which twitch only sends for the bot's own channel
+    pub redemptions: bool,
+    /// Reconnect if nothing, not even a keepalive, was received for this long
+    pub watchdog: std::time::Duration,
+    /// Ids of the last notifications, since twitch may deliver one more than once
+    pub seen: RecentIds,
+    /// Where reconnects are reported, if anywhere
+    pub monitor: Option<crate::webhook::Webhook>,
+    /// Beats for everything received from twitch, if there's a `--heartbeat-webhook`
+    pub heartbeat: Option<Arc<crate::webhook::Heartbeat>>,
+    /// Reconnects since the bot started, shared with the bot for `?!diag`
+    pub reconnects: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    /// Http proxy to connect through, if any
+    pub proxy: Option<url::Url>,
+    /// How the subscriptions a session already has are found
+    pub resubscribe: Resubscribe,
+    /// Subscriptions the bot created, by session, for `--resubscribe track`
+    pub subscribed: Vec<(String, eventsub::EventType, serde_json::Value)>,
+}
+
+/// The subscriptions of a session while it's subscribed to everything the bot needs.
+struct Subscriptions<'a> {
+    transport: eventsub::Transport,
+    token: &'a UserToken,
+    /// Type and condition of the subscriptions the session has, including the ones created since
+    existing: Vec<(eventsub::EventType, serde_json::Value)>,
+    /// Subscriptions the session already had
+    kept: usize,
+    /// Subscriptions created for the session
+    created: Vec<(eventsub::EventType, serde_json::Value)>,
+}
+
+/// Whether a subscription with `condition` is the one `wanted`, ignoring the fields twitch fills in as empty.
+fn same_condition(condition: &serde_json::Value, wanted: &serde_json::Value) -> bool {
+    let Some(wanted) = wanted.as_object() else {
+        return condition == wanted;
+    };
+    wanted
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .all(|(key, value)| condition.get(key) == Some(value))
+}
+
+/// Open a connection to `target` through an http proxy, with a `CONNECT` request.
+pub(crate) async fn tunnel(
+    proxy: &url::Url,
+    target: &url::Url,
+) -> Result<tokio::net::TcpStream, eyre::Report> {
+    use base64::Engine;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let host = proxy
+        .host_str()
+        .ok_or_else(|| eyre::eyre!("proxy has no host"))?;
+    let port = proxy.port_or_known_default().unwrap_or(80);
+    let target = format!(
+        "{}:{}",
+        target.host_str().unwrap_or_default(),
+        target.port_or_known_default().unwrap_or(443)
+    );
+    let mut stream = tokio::net::TcpStream::connect((host, port)).await?;
+
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if !proxy.username().is_empty() {
+        let decode = |s| percent_encoding::percent_decode_str(s).decode_utf8_lossy();
+        let credentials = format!(
+            "{}:{}",
+            decode(proxy.username()),
+            decode(proxy.password().unwrap_or_default())
+        );
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte, so nothing after the headers is taken from the tunnel.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            eyre::bail!("proxy sent an overly long response");
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        eyre::bail!("proxy refused the connection: {status}");
+    }
+    Ok(stream)
+}
+
+/// The last few message ids seen, forgetting the oldest once it's full.
+#[derive(Debug, Default)]
+pub struct RecentIds {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentIds {
+    /// How many ids are remembered, twitch resends notifications within seconds.
+    const CAPACITY: usize = 1000;
+
+    /// Remember an id, returning false if it was seen before.
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        if self.order.len() == Self::CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(id.to_owned());
+        self.ids.insert(id.to_owned());
+        true
+    }
 }
 
 impl ChatWebsocketClient {
@@ -41,15 +182,32 @@ impl ChatWebsocketClient {
     > {
         tracing::info!("connecting to twitch");
         let config = tungstenite::protocol::WebSocketConfig::default();
-        let (socket, _) =
-            tokio_tungstenite::connect_async_with_config(&self.connect_url, Some(config), false)
+        let (socket, _) = match &self.proxy {
+            Some(proxy) => {
+                let stream = tunnel(proxy, &self.connect_url)
+                    .await
+                    .wrap_err_with(|| format!("Can't connect through proxy {proxy}"))?;
+                tokio_tungstenite::client_async_tls_with_config(
+                    self.connect_url.as_str(),
+                    stream,
+                    Some(config),
+                    None,
+                )
                 .await
-                .wrap_err("Can't connect")?;
+            }
+            None => {
+                tokio_tungstenite::connect_async_with_config(&self.connect_url, Some(config), false)
+                    .await
+            }
+        }
+        .wrap_err("Can't connect")?;
 
         Ok(socket)
     }
 
     /// Run the websocket subscriber
+    ///
+    /// Returns once the connection is closed for good, e.g. after Twitch revoked a subscription.
     #[tracing::instrument(name = "subscriber", skip_all, fields())]
     pub async fn run<Fut>(
         mut self,
@@ -64,7 +222,25 @@ impl ChatWebsocketClient {
             .await
             .context("when establishing connection")?;
         // Loop over the stream, processing messages as they come in.
-        while let Some(msg) = futures::StreamExt::next(&mut s).await {
+        loop {
+            let msg =
+                match tokio::time::timeout(self.watchdog, futures::StreamExt::next(&mut s)).await {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    Err(_) => {
+                        // Twitch sends keepalives when there's nothing else, so the connection is stuck.
+                        tracing::warn!(
+                            "nothing received from twitch for {}s, reestablishing the connection",
+                            self.watchdog.as_secs()
+                        );
+                        self.report_reconnect("nothing received from twitch");
+                        s = self
+                            .connect()
+                            .await
+                            .context("when reestablishing connection")?;
+                        continue;
+                    }
+                };
             let span = tracing::debug_span!("message received", raw_message = ?msg);
             let msg = match msg {
                 Err(tungstenite::Error::Protocol(
@@ -73,6 +249,7 @@ impl ChatWebsocketClient {
                     tracing::warn!(
                         "connection was sent an unexpected frame or was reset, reestablishing it"
                     );
+                    self.report_reconnect("connection was reset");
                     s = self
                         .connect()
                         .instrument(span)
@@ -82,19 +259,39 @@ impl ChatWebsocketClient {
                 }
                 _ => msg.context("when getting message")?,
             };
-            self.process_message(msg, &mut event_fn)
+            if let tungstenite::Message::Close(frame) = &msg {
+                tracing::warn!(?frame, "twitch closed the connection, reestablishing it");
+                self.report_reconnect("twitch closed the connection");
+                s = self
+                    .connect()
+                    .instrument(span)
+                    .await
+                    .context("when reestablishing connection")?;
+                continue;
+            }
+            if self
+                .process_message(msg, &mut event_fn)
                 .instrument(span)
                 .await?
+                .is_break()
+            {
+                break;
+            }
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
         }
         Ok(())
     }
 
     /// Process a message from the websocket
+    ///
+    /// Breaks when the connection should not be used anymore.
     async fn process_message<Fut>(
         &mut self,
         msg: tungstenite::Message,
         event_fn: &mut impl FnMut(Event, types::Timestamp) -> Fut,
-    ) -> Result<(), eyre::Report>
+    ) -> Result<ControlFlow<()>, eyre::Report>
     where
         Fut: std::future::Future<Output = Result<(), eyre::Report>>,
     {
@@ -102,35 +299,147 @@ impl ChatWebsocketClient {
             tungstenite::Message::Text(s) => {
                 tracing::trace!("{s}");
                 // Parse the message into a [twitch_api::eventsub::EventsubWebsocketData]
-                match Event::parse_websocket(&s)? {
+                let data = match Event::parse_websocket(&s) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        // Most likely a message type twitch added after this bot was built,
+                        // which is no reason to drop the connection.
+                        tracing::warn!("skipping websocket frame that couldn't be parsed: {e}");
+                        tracing::debug!(frame = %s, "unparsable websocket frame");
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                };
+                match data {
                     EventsubWebsocketData::Welcome {
                         payload: WelcomePayload { session },
                         ..
+                    } => {
+                        self.process_welcome_message(session).await?;
+                        Ok(ControlFlow::Continue(()))
                     }
-                    | EventsubWebsocketData::Reconnect {
+                    EventsubWebsocketData::Reconnect {
                         payload: ReconnectPayload { session },
                         ..
                     } => {
+                        self.report_reconnect("twitch asked the bot to reconnect");
                         self.process_welcome_message(session).await?;
-                        Ok(())
+                        Ok(ControlFlow::Continue(()))
                     }
                     EventsubWebsocketData::Notification { metadata, payload } => {
+                        if !self.seen.insert(&metadata.message_id) {
+                            tracing::info!(
+                                message_id = %metadata.message_id,
+                                subscription_type = %metadata.subscription_type,
+                                "dropping a notification twitch delivered twice"
+                            );
+                            return Ok(ControlFlow::Continue(()));
+                        }
                         event_fn(payload, metadata.message_timestamp.into_owned()).await?;
-                        Ok(())
+                        Ok(ControlFlow::Continue(()))
                     }
-                    re @ EventsubWebsocketData::Revocation { .. } => {
-                        eyre::bail!("got revocation event: {re:?}")
+                    EventsubWebsocketData::Revocation { metadata, payload } => {
+                        // Without the subscription the bot would stay connected but never see chat,
+                        // so stop instead and let the operator authorize it again.
+                        let reason = payload
+                            .subscription()
+                            .map(|s| format!("{:?}", s.status))
+                            .unwrap_or_else(|_| "unknown".to_owned());
+                        tracing::error!(
+                            subscription_type = %metadata.subscription_type,
+                            %reason,
+                            "twitch revoked a subscription, shutting down. Restart the bot to authorize it again"
+                        );
+                        Ok(ControlFlow::Break(()))
                     }
                     EventsubWebsocketData::Keepalive {
                         metadata: _,
                         payload: _,
-                    } => Ok(()),
-                    _ => Ok(()),
+                    } => Ok(ControlFlow::Continue(())),
+                    _ => Ok(ControlFlow::Continue(())),
                 }
             }
-            tungstenite::Message::Close(_) => todo!(),
-            _ => Ok(()),
+            _ => Ok(ControlFlow::Continue(())),
+        }
+    }
+
+    /// Count a reconnect and post it to the `--monitoring-webhook`.
+    fn report_reconnect(&mut self, reason: &str) {
+        let attempt = self
+            .reconnects
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        if let Some(monitor) = &self.monitor {
+            monitor.post(&crate::webhook::MonitorEvent {
+                kind: "reconnect",
+                reason,
+                attempt,
+            });
+        }
+    }
+
+    /// Subscribe the session to an event unless it is already, retrying if twitch has trouble with it.
+    async fn subscribe<E: eventsub::EventSubscription + Clone + Send>(
+        &self,
+        subscription: E,
+        subscriptions: &mut Subscriptions<'_>,
+    ) -> Result<(), eyre::Report> {
+        let condition = subscription.condition()?;
+        if subscriptions
+            .existing
+            .iter()
+            .any(|(kind, existing)| *kind == E::EVENT_TYPE && same_condition(existing, &condition))
+        {
+            tracing::debug!(subscription_type = %E::EVENT_TYPE, %condition, "already subscribed");
+            subscriptions.kept += 1;
+            return Ok(());
         }
+        crate::retry::helix("subscribing to events", || {
+            self.client.create_eventsub_subscription(
+                subscription.clone(),
+                subscriptions.transport.clone(),
+                subscriptions.token,
+            )
+        })
+        .await?;
+        tracing::debug!(subscription_type = %E::EVENT_TYPE, %condition, "subscribed");
+        subscriptions
+            .existing
+            .push((E::EVENT_TYPE, condition.clone()));
+        subscriptions.created.push((E::EVENT_TYPE, condition));
+        Ok(())
+    }
+
+    /// Type and condition of the subscriptions `session` has.
+    async fn existing_subscriptions(
+        &self,
+        session: &str,
+        token: &UserToken,
+    ) -> Result<Vec<(eventsub::EventType, serde_json::Value)>, eyre::Report> {
+        if self.resubscribe == Resubscribe::Track {
+            return Ok(self
+                .subscribed
+                .iter()
+                .filter(|(id, _, _)| id == session)
+                .map(|(_, kind, condition)| (*kind, condition.clone()))
+                .collect());
+        }
+        self.client
+            .get_eventsub_subscriptions(Some(eventsub::Status::Enabled), None, None, token)
+            .map_ok(|r| {
+                futures::stream::iter(
+                    r.subscriptions
+                        .into_iter()
+                        .filter(|s| {
+                            s.transport
+                                .as_websocket()
+                                .is_some_and(|t| t.session_id == session)
+                        })
+                        .map(|s| Ok::<_, eyre::Report>((s.type_, s.condition))),
+                )
+            })
+            .try_flatten()
+            .try_collect()
+            .await
     }
 
     async fn process_welcome_message(&mut self, data: SessionData<'_>) -> Result<(), eyre::Report> {
@@ -140,43 +449,120 @@ impl ChatWebsocketClient {
             self.connect_url = url.parse()?;
         }
         let token = self.token.lock().await;
-        let transport = eventsub::Transport::websocket(data.id.clone());
+        let mut subscriptions = Subscriptions {
+            transport: eventsub::Transport::websocket(data.id.clone()),
+            token: &token,
+            existing: self.existing_subscriptions(&data.id, &token).await?,
+            kept: 0,
+            created: Vec::new(),
+        };
+        let user_id = token.user_id().unwrap().to_owned();
         for id in &self.chats {
-            let user_id = token.user_id().unwrap().to_owned();
-            let subs: Vec<_> = self
-                .client
-                .get_eventsub_subscriptions(Some(eventsub::Status::Enabled), None, None, &*token)
-                .map_ok(|r| {
-                    futures::stream::iter(
-                        r.subscriptions
-                            .into_iter()
-                            .filter(|s| {
-                                s.transport
-                                    .as_websocket()
-                                    .is_some_and(|t| t.session_id == data.id)
-                            })
-                            .map(Ok::<_, eyre::Report>),
-                    )
-                })
-                .try_flatten()
-                .try_collect()
+            self.subscribe(
+                eventsub::channel::chat::ChannelChatMessageV1::new(id.clone(), user_id.clone()),
+                &mut subscriptions,
+            )
+            .await?;
+            self.subscribe(
+                eventsub::channel::chat::ChannelChatNotificationV1::new(
+                    id.clone(),
+                    user_id.clone(),
+                ),
+                &mut subscriptions,
+            )
+            .await?;
+            // Only the bot's own messages, holds of other people's need a moderator scope.
+            self.subscribe(
+                eventsub::channel::ChannelChatUserMessageHoldV1::new(id.clone(), user_id.clone()),
+                &mut subscriptions,
+            )
+            .await?;
+            self.subscribe(
+                eventsub::channel::ChannelChatUserMessageUpdateV1::new(id.clone(), user_id.clone()),
+                &mut subscriptions,
+            )
+            .await?;
+            self.subscribe(
+                eventsub::channel::ChannelChatSettingsUpdateV1::new(id.clone(), user_id.clone()),
+                &mut subscriptions,
+            )
+            .await?;
+            if self.redemptions && *id == user_id {
+                self.subscribe(
+                    eventsub::channel::ChannelPointsCustomRewardRedemptionAddV1::broadcaster_user_id(
+                        id.clone(),
+                    ),
+                    &mut subscriptions,
+                )
                 .await?;
-            if !subs.is_empty() {
-                continue;
+            } else if self.redemptions {
+                tracing::warn!(
+                    broadcaster = %id,
+                    "the bot doesn't run as this broadcaster, redeeming a --spin-reward does nothing here"
+                );
             }
-            let message =
-                eventsub::channel::chat::ChannelChatMessageV1::new(id.clone(), user_id.clone());
-            self.client
-                .create_eventsub_subscription(message, transport.clone(), &*token)
+            if self.clears {
+                self.subscribe(
+                    eventsub::channel::ChannelChatClearUserMessagesV1::new(
+                        id.clone(),
+                        user_id.clone(),
+                    ),
+                    &mut subscriptions,
+                )
                 .await?;
-            self.client
-                .create_eventsub_subscription(
-                    eventsub::channel::chat::ChannelChatNotificationV1::new(id.clone(), user_id),
-                    transport.clone(),
-                    &*token,
+            }
+            if self.stream_events {
+                self.subscribe(
+                    eventsub::stream::StreamOnlineV1::broadcaster_user_id(id.clone()),
+                    &mut subscriptions,
                 )
                 .await?;
+                self.subscribe(
+                    eventsub::stream::StreamOfflineV1::broadcaster_user_id(id.clone()),
+                    &mut subscriptions,
+                )
+                .await?;
+            }
+        }
+        tracing::info!(
+            session = %data.id,
+            kept = subscriptions.kept,
+            created = subscriptions.created.len(),
+            "subscriptions reconciled"
+        );
+        if self.resubscribe == Resubscribe::Track {
+            let created = std::mem::take(&mut subscriptions.created);
+            // Subscriptions of older sessions ended with them.
+            self.subscribed
+                .retain(|(session, _, _)| *session == *data.id);
+            self.subscribed.extend(
+                created
+                    .into_iter()
+                    .map(|(kind, condition)| (data.id.to_string(), kind, condition)),
+            );
+        }
+        if !self.welcome_messages.is_empty() && (!self.welcomed || self.welcome_on_reconnect) {
+            let user_id = token.user_id().unwrap();
+            for id in &self.chats {
+                let Some(message) = self.welcome_messages.get(id) else {
+                    continue;
+                };
+                let response = crate::retry::helix("posting the welcome message", || {
+                    self.client
+                        .send_chat_message(id, user_id, message.as_str(), &*token)
+                })
+                .await
+                .wrap_err("when posting welcome message")?;
+                if !response.is_sent {
+                    tracing::error!(
+                        broadcaster = %id,
+                        reason = ?response.drop_reason.map(|r| r.message),
+                        "twitch dropped the welcome message"
+                    );
+                }
+            }
+            self.welcomed = true;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}